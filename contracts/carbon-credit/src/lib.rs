@@ -1,5 +1,5 @@
 #![no_std]
-use soroban_sdk::{contract, contractimpl, contracttype, token, Address, Env, String, Symbol};
+use soroban_sdk::{contract, contracterror, contractimpl, contracttype, Address, Env, Map, String, Symbol, Vec};
 
 #[derive(Clone)]
 #[contracttype]
@@ -9,8 +9,15 @@ pub enum DataKey {
     CreditInfo(u64), // credit_id -> CreditMetadata
     CreditOwner(u64), // credit_id -> Owner address
     OwnerCredits(Address), // owner -> Vec<credit_id>
+    OwnerBalance(Address), // owner -> total tons held
     NextCreditId,
     VerificationContract,
+    NextSerial(String, u32), // (project_id, vintage_year) -> next unassigned serial
+    IssuanceBlocks(String, u32), // (project_id, vintage_year) -> Vec<u64> of issuance block starts, ascending
+    SerialIndex(String, u32, u64), // (project_id, vintage_year, issuance block start) -> Vec<SerialBlock> sorted by start
+    Retirement(u64), // retirement_id -> RetirementRecord
+    NextRetirementId,
+    Broker(Address, Address), // (owner, broker) -> approved to retire on the owner's behalf
 }
 
 #[derive(Clone)]
@@ -24,6 +31,53 @@ pub struct CreditMetadata {
     pub verification_status: VerificationStatus,
     pub issuer: Address,
     pub created_at: u64,
+    pub parent_ids: Vec<u64>, // Credits this one was split or merged from
+    pub serial_ranges: Vec<SerialRange>, // Serials held by this credit, sorted by start
+    pub royalty: Royalty,
+    pub verifier: Option<Address>, // Verifier who last reviewed the credit
+}
+
+/// Resale royalties in basis points of the sale price, fixed at issuance
+#[derive(Clone, Default, PartialEq, Debug)]
+#[contracttype]
+pub struct Royalty {
+    pub issuer_bps: u32,
+    pub verifier_bps: u32, // Paid to the credit's verifier, if one has reviewed it
+}
+
+/// Inclusive block of tonne serial numbers within a project vintage
+#[derive(Clone, PartialEq, Debug)]
+#[contracttype]
+pub struct SerialRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Durable retirement certificate
+#[derive(Clone)]
+#[contracttype]
+pub struct RetirementRecord {
+    pub retirement_id: u64,
+    pub credit_id: u64,
+    pub project_id: String,
+    pub vintage_year: u32,
+    pub owner: Address,
+    pub retired_by: Address, // Owner, or the broker retiring on their behalf
+    pub beneficiary: String, // Name or address the offset is claimed for
+    pub reason: String,
+    pub reporting_period: String,
+    pub amount_tons: u64,
+    pub serial_ranges: Vec<SerialRange>,
+    pub retired_at: u64,
+}
+
+/// Serial range entry in the per-vintage lookup index
+#[derive(Clone)]
+#[contracttype]
+pub struct SerialBlock {
+    pub start: u64,
+    pub end: u64,
+    pub credit_id: u64,
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -34,23 +88,52 @@ pub enum VerificationStatus {
     Rejected,
 }
 
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    AlreadyInitialized = 1,
+    NotInitialized = 2,
+    CreditNotFound = 3,
+    NotOwner = 4,
+    CreditNotActive = 5, // Credit has been retired, split or merged
+    NotVerified = 6,
+    InvalidAmount = 7,
+    AmountMismatch = 8,
+    IncompatibleCredits = 9,
+    SerialNotFound = 10,
+    InvalidProjectId = 11,
+    NotAuthorized = 12,
+    RetirementNotFound = 13,
+    RoyaltyTooHigh = 14,
+}
+
+/// Maximum project ID length, keeps formatted serial numbers bounded
+const MAX_PROJECT_ID_LEN: u32 = 64;
+
+/// Maximum combined resale royalty (10%)
+const MAX_ROYALTY_BPS: u32 = 1000;
+
 #[contract]
 pub struct CarbonCreditContract;
 
 #[contractimpl]
 impl CarbonCreditContract {
     /// Initialize the contract with admin
-    pub fn initialize(env: Env, admin: Address, verification_contract: Address) {
+    pub fn initialize(env: Env, admin: Address, verification_contract: Address) -> Result<(), Error> {
         if env.storage().instance().has(&DataKey::Admin) {
-            panic!("Already initialized");
+            return Err(Error::AlreadyInitialized);
         }
         
         env.storage().instance().set(&DataKey::Admin, &admin);
         env.storage().instance().set(&DataKey::NextCreditId, &1u64);
+        env.storage().instance().set(&DataKey::NextRetirementId, &1u64);
         env.storage().instance().set(&DataKey::VerificationContract, &verification_contract);
+        
+        Ok(())
     }
 
-    /// Issue a new carbon credit
+    /// Issue a new carbon credit with the resale royalty it carries for life
     pub fn issue_credit(
         env: Env,
         issuer: Address,
@@ -58,10 +141,36 @@ impl CarbonCreditContract {
         project_name: String,
         vintage_year: u32,
         amount_tons: u64,
-    ) -> u64 {
+        royalty: Royalty,
+    ) -> Result<u64, Error> {
         issuer.require_auth();
         
-        let credit_id: u64 = env.storage().instance().get(&DataKey::NextCreditId).unwrap();
+        if amount_tons == 0 {
+            return Err(Error::InvalidAmount);
+        }
+        
+        let royalty_bps = royalty.issuer_bps.checked_add(royalty.verifier_bps).ok_or(Error::RoyaltyTooHigh)?;
+        if royalty_bps > MAX_ROYALTY_BPS {
+            return Err(Error::RoyaltyTooHigh);
+        }
+        
+        if project_id.is_empty() || project_id.len() > MAX_PROJECT_ID_LEN {
+            return Err(Error::InvalidProjectId);
+        }
+        
+        let credit_id = Self::next_credit_id(&env)?;
+        
+        // Assign the next block of serials for this project vintage
+        let serial_key = DataKey::NextSerial(project_id.clone(), vintage_year);
+        let start: u64 = env.storage().persistent().get(&serial_key).unwrap_or(1);
+        let end = start.checked_add(amount_tons - 1).ok_or(Error::InvalidAmount)?;
+        env.storage().persistent().set(&serial_key, &(end + 1));
+        
+        // Each issuance gets its own serial index shard, so splits only rewrite that shard
+        let blocks_key = DataKey::IssuanceBlocks(project_id.clone(), vintage_year);
+        let mut issuance_blocks: Vec<u64> = env.storage().persistent().get(&blocks_key).unwrap_or(Vec::new(&env));
+        issuance_blocks.push_back(start);
+        env.storage().persistent().set(&blocks_key, &issuance_blocks);
         
         let metadata = CreditMetadata {
             credit_id,
@@ -72,21 +181,24 @@ impl CarbonCreditContract {
             verification_status: VerificationStatus::Pending,
             issuer: issuer.clone(),
             created_at: env.ledger().timestamp(),
+            parent_ids: Vec::new(&env),
+            serial_ranges: Vec::from_array(&env, [SerialRange { start, end }]),
+            royalty,
+            verifier: None,
         };
         
         env.storage().persistent().set(&DataKey::CreditInfo(credit_id), &metadata);
-        env.storage().persistent().set(&DataKey::CreditOwner(credit_id), &issuer);
-        
-        // Update next credit ID
-        env.storage().instance().set(&DataKey::NextCreditId, &(credit_id + 1));
+        Self::assign_owner(&env, credit_id, &issuer, amount_tons);
+        Self::index_serials(&env, &metadata, &Vec::new(&env), &Vec::from_array(&env, [metadata.clone()]));
         
         // Emit event
+        #[allow(deprecated)]
         env.events().publish(
             (Symbol::new(&env, "credit_issued"),),
             (credit_id, issuer, amount_tons)
         );
         
-        credit_id
+        Ok(credit_id)
     }
 
     /// Update verification status (called by verification contract)
@@ -94,86 +206,240 @@ impl CarbonCreditContract {
         env: Env,
         credit_id: u64,
         status: VerificationStatus,
-    ) {
+        verifier: Address,
+    ) -> Result<(), Error> {
         let verification_contract: Address = env.storage()
             .instance()
             .get(&DataKey::VerificationContract)
-            .unwrap();
+            .ok_or(Error::NotInitialized)?;
         
         verification_contract.require_auth();
         
-        let mut metadata: CreditMetadata = env.storage()
-            .persistent()
-            .get(&DataKey::CreditInfo(credit_id))
-            .unwrap();
+        let mut metadata = Self::load_credit(&env, credit_id)?;
         
         metadata.verification_status = status.clone();
+        metadata.verifier = Some(verifier);
         env.storage().persistent().set(&DataKey::CreditInfo(credit_id), &metadata);
         
+        #[allow(deprecated)]
         env.events().publish(
             (Symbol::new(&env, "verification_updated"),),
             (credit_id, status)
         );
+        
+        Ok(())
     }
 
     /// Transfer credit ownership
-    pub fn transfer(env: Env, from: Address, to: Address, credit_id: u64) {
+    pub fn transfer(env: Env, from: Address, to: Address, credit_id: u64) -> Result<(), Error> {
         from.require_auth();
         
-        let current_owner: Address = env.storage()
-            .persistent()
-            .get(&DataKey::CreditOwner(credit_id))
-            .unwrap();
-        
-        if current_owner != from {
-            panic!("Not the owner");
-        }
+        let metadata = Self::require_owner(&env, credit_id, &from)?;
         
-        env.storage().persistent().set(&DataKey::CreditOwner(credit_id), &to);
+        Self::release_owner(&env, credit_id, &from, metadata.amount_tons);
+        Self::assign_owner(&env, credit_id, &to, metadata.amount_tons);
         
+        #[allow(deprecated)]
         env.events().publish(
             (Symbol::new(&env, "credit_transferred"),),
             (credit_id, from, to)
         );
+        
+        Ok(())
     }
 
-    /// Retire a carbon credit (permanently removes from circulation)
-    pub fn retire_credit(env: Env, owner: Address, credit_id: u64) {
+    /// Retire a carbon credit (permanently removes from circulation) and record a certificate
+    pub fn retire_credit(
+        env: Env,
+        owner: Address,
+        credit_id: u64,
+        beneficiary: String,
+        reason: String,
+        reporting_period: String,
+    ) -> Result<u64, Error> {
         owner.require_auth();
         
-        let current_owner: Address = env.storage()
-            .persistent()
-            .get(&DataKey::CreditOwner(credit_id))
-            .unwrap();
+        Self::retire(&env, &owner, &owner, credit_id, beneficiary, reason, reporting_period)
+    }
+
+    /// Retire a client's credit as an approved broker
+    pub fn retire_on_behalf(
+        env: Env,
+        broker: Address,
+        owner: Address,
+        credit_id: u64,
+        beneficiary: String,
+        reason: String,
+        reporting_period: String,
+    ) -> Result<u64, Error> {
+        broker.require_auth();
         
-        if current_owner != owner {
-            panic!("Not the owner");
+        if !Self::is_broker(env.clone(), owner.clone(), broker.clone()) {
+            return Err(Error::NotAuthorized);
         }
         
-        let metadata: CreditMetadata = env.storage()
+        Self::retire(&env, &owner, &broker, credit_id, beneficiary, reason, reporting_period)
+    }
+
+    /// Approve or revoke a broker allowed to retire the owner's credits
+    pub fn set_broker(env: Env, owner: Address, broker: Address, approved: bool) {
+        owner.require_auth();
+        
+        let key = DataKey::Broker(owner.clone(), broker.clone());
+        if approved {
+            env.storage().persistent().set(&key, &true);
+        } else {
+            env.storage().persistent().remove(&key);
+        }
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "broker_updated"),),
+            (owner, broker, approved)
+        );
+    }
+
+    /// Check if a broker may retire the owner's credits
+    pub fn is_broker(env: Env, owner: Address, broker: Address) -> bool {
+        env.storage()
             .persistent()
-            .get(&DataKey::CreditInfo(credit_id))
-            .unwrap();
+            .get(&DataKey::Broker(owner, broker))
+            .unwrap_or(false)
+    }
+
+    /// Get a retirement certificate
+    pub fn get_retirement(env: Env, retirement_id: u64) -> Result<RetirementRecord, Error> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Retirement(retirement_id))
+            .ok_or(Error::RetirementNotFound)
+    }
+
+    /// Split a credit into child credits whose amounts add up to the parent's tonnage
+    pub fn split_credit(env: Env, owner: Address, credit_id: u64, amounts: Vec<u64>) -> Result<Vec<u64>, Error> {
+        owner.require_auth();
         
-        if metadata.verification_status != VerificationStatus::Verified {
-            panic!("Only verified credits can be retired");
+        let metadata = Self::require_owner(&env, credit_id, &owner)?;
+        
+        if amounts.len() < 2 {
+            return Err(Error::InvalidAmount);
         }
         
-        // Mark as retired by removing ownership
-        env.storage().persistent().remove(&DataKey::CreditOwner(credit_id));
+        let mut total: u64 = 0;
+        for amount in amounts.iter() {
+            if amount == 0 {
+                return Err(Error::InvalidAmount);
+            }
+            total = total.checked_add(amount).ok_or(Error::AmountMismatch)?;
+        }
+        
+        if total != metadata.amount_tons {
+            return Err(Error::AmountMismatch);
+        }
         
+        // The parent leaves circulation and is replaced by its children
+        Self::release_owner(&env, credit_id, &owner, metadata.amount_tons);
+        
+        // Children take consecutive, non-overlapping slices of the parent's serials
+        let mut child_ids: Vec<u64> = Vec::new(&env);
+        let mut children: Vec<CreditMetadata> = Vec::new(&env);
+        let mut offset: u64 = 0;
+        for amount in amounts.iter() {
+            let child_id = Self::next_credit_id(&env)?;
+            
+            let mut child = metadata.clone();
+            child.credit_id = child_id;
+            child.amount_tons = amount;
+            child.created_at = env.ledger().timestamp();
+            child.parent_ids = Vec::from_array(&env, [credit_id]);
+            child.serial_ranges = Self::slice_serials(&env, &metadata.serial_ranges, offset, amount);
+            offset += amount;
+            
+            env.storage().persistent().set(&DataKey::CreditInfo(child_id), &child);
+            Self::assign_owner(&env, child_id, &owner, amount);
+            child_ids.push_back(child_id);
+            children.push_back(child);
+        }
+        
+        Self::index_serials(&env, &metadata, &Vec::from_array(&env, [credit_id]), &children);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "credit_split"),),
+            (credit_id, owner, child_ids.clone())
+        );
+        
+        Ok(child_ids)
+    }
+
+    /// Merge credits of the same project and vintage into a single credit
+    pub fn merge_credits(env: Env, owner: Address, credit_ids: Vec<u64>) -> Result<u64, Error> {
+        owner.require_auth();
+        
+        if credit_ids.len() < 2 {
+            return Err(Error::InvalidAmount);
+        }
+        
+        let first = Self::require_owner(&env, credit_ids.get_unchecked(0), &owner)?;
+        let mut total = first.amount_tons;
+        let mut serial_ranges = first.serial_ranges.clone();
+        let mut sources: Vec<CreditMetadata> = Vec::from_array(&env, [first.clone()]);
+        
+        for (i, credit_id) in credit_ids.iter().enumerate().skip(1) {
+            // A credit cannot be merged with itself
+            if credit_ids.iter().take(i).any(|id| id == credit_id) {
+                return Err(Error::IncompatibleCredits);
+            }
+            
+            let metadata = Self::require_owner(&env, credit_id, &owner)?;
+            
+            if metadata.project_id != first.project_id
+                || metadata.vintage_year != first.vintage_year
+                || metadata.verification_status != first.verification_status
+                || metadata.issuer != first.issuer
+                || metadata.royalty != first.royalty
+                || metadata.verifier != first.verifier
+            {
+                return Err(Error::IncompatibleCredits);
+            }
+            
+            total = total.checked_add(metadata.amount_tons).ok_or(Error::InvalidAmount)?;
+            for range in metadata.serial_ranges.iter() {
+                serial_ranges.push_back(range);
+            }
+            sources.push_back(metadata);
+        }
+        
+        // The merged credits leave circulation and are replaced by the new one
+        for source in sources.iter() {
+            Self::release_owner(&env, source.credit_id, &owner, source.amount_tons);
+        }
+        
+        let merged_id = Self::next_credit_id(&env)?;
+        
+        let mut merged = first;
+        merged.credit_id = merged_id;
+        merged.amount_tons = total;
+        merged.created_at = env.ledger().timestamp();
+        merged.parent_ids = credit_ids.clone();
+        merged.serial_ranges = Self::coalesce_serials(&env, &serial_ranges);
+        
+        env.storage().persistent().set(&DataKey::CreditInfo(merged_id), &merged);
+        Self::assign_owner(&env, merged_id, &owner, total);
+        Self::index_serials(&env, &merged, &credit_ids, &Vec::from_array(&env, [merged.clone()]));
+        
+        #[allow(deprecated)]
         env.events().publish(
-            (Symbol::new(&env, "credit_retired"),),
-            (credit_id, owner, metadata.amount_tons)
+            (Symbol::new(&env, "credits_merged"),),
+            (merged_id, owner, credit_ids)
         );
+        
+        Ok(merged_id)
     }
 
     /// Get credit metadata
-    pub fn get_credit(env: Env, credit_id: u64) -> CreditMetadata {
-        env.storage()
-            .persistent()
-            .get(&DataKey::CreditInfo(credit_id))
-            .unwrap()
+    pub fn get_credit(env: Env, credit_id: u64) -> Result<CreditMetadata, Error> {
+        Self::load_credit(&env, credit_id)
     }
 
     /// Get credit owner
@@ -183,14 +449,373 @@ impl CarbonCreditContract {
             .get(&DataKey::CreditOwner(credit_id))
     }
 
+    /// Get the credit that currently holds a serial number within a project vintage
+    pub fn get_credit_by_serial(env: Env, project_id: String, vintage_year: u32, serial: u64) -> Result<u64, Error> {
+        let issuance_blocks: Vec<u64> = env.storage()
+            .persistent()
+            .get(&DataKey::IssuanceBlocks(project_id.clone(), vintage_year))
+            .ok_or(Error::SerialNotFound)?;
+        
+        let shard = Self::find_issuance_block(&issuance_blocks, serial).ok_or(Error::SerialNotFound)?;
+        let index: Vec<SerialBlock> = env.storage()
+            .persistent()
+            .get(&DataKey::SerialIndex(project_id, vintage_year, issuance_blocks.get_unchecked(shard)))
+            .ok_or(Error::SerialNotFound)?;
+        
+        // Binary search for the last block starting at or before the serial
+        let mut low: u32 = 0;
+        let mut high: u32 = index.len();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if index.get_unchecked(mid).start <= serial {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        
+        if low == 0 {
+            return Err(Error::SerialNotFound);
+        }
+        
+        let block = index.get_unchecked(low - 1);
+        if serial > block.end {
+            return Err(Error::SerialNotFound);
+        }
+        
+        Ok(block.credit_id)
+    }
+
+    /// Get registry-style serial numbers for a credit, e.g. `VCS-1234-2024-00001-05000`
+    pub fn get_serial_numbers(env: Env, credit_id: u64) -> Result<Vec<String>, Error> {
+        let metadata = Self::load_credit(&env, credit_id)?;
+        
+        let mut serials: Vec<String> = Vec::new(&env);
+        for range in metadata.serial_ranges.iter() {
+            serials.push_back(Self::format_serial(&env, &metadata.project_id, metadata.vintage_year, &range));
+        }
+        
+        Ok(serials)
+    }
+
+    /// Get a page of the credits currently held by an owner
+    pub fn get_credits_by_owner(env: Env, owner: Address, start: u32, limit: u32) -> Vec<CreditMetadata> {
+        let credit_ids: Vec<u64> = env.storage()
+            .persistent()
+            .get(&DataKey::OwnerCredits(owner))
+            .unwrap_or(Vec::new(&env));
+        
+        let mut credits: Vec<CreditMetadata> = Vec::new(&env);
+        for credit_id in credit_ids.iter().skip(start as usize).take(limit as usize) {
+            if let Some(metadata) = env.storage().persistent().get(&DataKey::CreditInfo(credit_id)) {
+                credits.push_back(metadata);
+            }
+        }
+        
+        credits
+    }
+
+    /// Get the total tons currently held by an owner
+    pub fn balance_tons(env: Env, owner: Address) -> u64 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::OwnerBalance(owner))
+            .unwrap_or(0)
+    }
+
     /// Check if credit is verified
-    pub fn is_verified(env: Env, credit_id: u64) -> bool {
-        let metadata: CreditMetadata = env.storage()
+    pub fn is_verified(env: Env, credit_id: u64) -> Result<bool, Error> {
+        let metadata = Self::load_credit(&env, credit_id)?;
+        
+        Ok(metadata.verification_status == VerificationStatus::Verified)
+    }
+
+    // Helper function to retire a credit held by `owner` and store its certificate
+    fn retire(
+        env: &Env,
+        owner: &Address,
+        retired_by: &Address,
+        credit_id: u64,
+        beneficiary: String,
+        reason: String,
+        reporting_period: String,
+    ) -> Result<u64, Error> {
+        let metadata = Self::require_owner(env, credit_id, owner)?;
+        
+        // Only verified credits can be retired
+        if metadata.verification_status != VerificationStatus::Verified {
+            return Err(Error::NotVerified);
+        }
+        
+        let retirement_id: u64 = env.storage()
+            .instance()
+            .get(&DataKey::NextRetirementId)
+            .ok_or(Error::NotInitialized)?;
+        env.storage().instance().set(&DataKey::NextRetirementId, &(retirement_id + 1));
+        
+        let record = RetirementRecord {
+            retirement_id,
+            credit_id,
+            project_id: metadata.project_id,
+            vintage_year: metadata.vintage_year,
+            owner: owner.clone(),
+            retired_by: retired_by.clone(),
+            beneficiary,
+            reason,
+            reporting_period,
+            amount_tons: metadata.amount_tons,
+            serial_ranges: metadata.serial_ranges,
+            retired_at: env.ledger().timestamp(),
+        };
+        
+        env.storage().persistent().set(&DataKey::Retirement(retirement_id), &record);
+        
+        // Mark as retired by removing ownership
+        Self::release_owner(env, credit_id, owner, metadata.amount_tons);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(env, "credit_retired"),),
+            (credit_id, owner.clone(), metadata.amount_tons, retirement_id)
+        );
+        
+        Ok(retirement_id)
+    }
+
+    // Helper function to record `owner` as holder of a credit in the owner index
+    fn assign_owner(env: &Env, credit_id: u64, owner: &Address, tons: u64) {
+        env.storage().persistent().set(&DataKey::CreditOwner(credit_id), owner);
+        
+        let credits_key = DataKey::OwnerCredits(owner.clone());
+        let mut credit_ids: Vec<u64> = env.storage().persistent().get(&credits_key).unwrap_or(Vec::new(env));
+        credit_ids.push_back(credit_id);
+        env.storage().persistent().set(&credits_key, &credit_ids);
+        
+        let balance_key = DataKey::OwnerBalance(owner.clone());
+        let balance: u64 = env.storage().persistent().get(&balance_key).unwrap_or(0);
+        env.storage().persistent().set(&balance_key, &(balance + tons));
+    }
+
+    // Helper function to drop a credit from its holder's index, leaving it without an owner
+    fn release_owner(env: &Env, credit_id: u64, owner: &Address, tons: u64) {
+        env.storage().persistent().remove(&DataKey::CreditOwner(credit_id));
+        
+        let credits_key = DataKey::OwnerCredits(owner.clone());
+        let mut credit_ids: Vec<u64> = env.storage().persistent().get(&credits_key).unwrap_or(Vec::new(env));
+        if let Some(index) = credit_ids.first_index_of(credit_id) {
+            credit_ids.remove(index);
+        }
+        env.storage().persistent().set(&credits_key, &credit_ids);
+        
+        let balance_key = DataKey::OwnerBalance(owner.clone());
+        let balance: u64 = env.storage().persistent().get(&balance_key).unwrap_or(0);
+        env.storage().persistent().set(&balance_key, &balance.saturating_sub(tons));
+    }
+
+    // Helper function to allocate the next credit ID
+    fn next_credit_id(env: &Env) -> Result<u64, Error> {
+        let credit_id: u64 = env.storage()
+            .instance()
+            .get(&DataKey::NextCreditId)
+            .ok_or(Error::NotInitialized)?;
+        
+        env.storage().instance().set(&DataKey::NextCreditId, &(credit_id + 1));
+        
+        Ok(credit_id)
+    }
+
+    // Helper function to take `amount` serials from `ranges`, skipping the first `offset`
+    fn slice_serials(env: &Env, ranges: &Vec<SerialRange>, offset: u64, amount: u64) -> Vec<SerialRange> {
+        let mut slice: Vec<SerialRange> = Vec::new(env);
+        let mut skip = offset;
+        let mut needed = amount;
+        
+        for range in ranges.iter() {
+            if needed == 0 {
+                break;
+            }
+            
+            let size = range.end - range.start + 1;
+            if skip >= size {
+                skip -= size;
+                continue;
+            }
+            
+            let start = range.start + skip;
+            let take = core::cmp::min(size - skip, needed);
+            slice.push_back(SerialRange { start, end: start + take - 1 });
+            
+            needed -= take;
+            skip = 0;
+        }
+        
+        slice
+    }
+
+    // Helper function to sort serial ranges and join adjacent ones
+    fn coalesce_serials(env: &Env, ranges: &Vec<SerialRange>) -> Vec<SerialRange> {
+        let mut sorted: Vec<SerialRange> = Vec::new(env);
+        for range in ranges.iter() {
+            let mut pos = sorted.len();
+            while pos > 0 && sorted.get_unchecked(pos - 1).start > range.start {
+                pos -= 1;
+            }
+            sorted.insert(pos, range);
+        }
+        
+        let mut coalesced: Vec<SerialRange> = Vec::new(env);
+        for range in sorted.iter() {
+            let last_index = coalesced.len();
+            if last_index > 0 {
+                let mut last = coalesced.get_unchecked(last_index - 1);
+                if last.end + 1 == range.start {
+                    last.end = range.end;
+                    coalesced.set(last_index - 1, last);
+                    continue;
+                }
+            }
+            coalesced.push_back(range);
+        }
+        
+        coalesced
+    }
+
+    // Helper function to point the serial index of a project vintage at new holders,
+    // dropping the blocks of `replaced` credits and inserting those of `credits`. Serials are
+    // conserved, so only the issuance shards covered by `credits` are touched.
+    fn index_serials(env: &Env, template: &CreditMetadata, replaced: &Vec<u64>, credits: &Vec<CreditMetadata>) {
+        let issuance_blocks: Vec<u64> = env.storage()
+            .persistent()
+            .get(&DataKey::IssuanceBlocks(template.project_id.clone(), template.vintage_year))
+            .unwrap_or(Vec::new(env));
+        
+        // Cut each range at issuance block boundaries, grouping the pieces by shard
+        let mut pieces: Map<u64, Vec<SerialBlock>> = Map::new(env);
+        for credit in credits.iter() {
+            for range in credit.serial_ranges.iter() {
+                let mut start = range.start;
+                while let Some(shard) = Self::find_issuance_block(&issuance_blocks, start) {
+                    let end = match issuance_blocks.get(shard + 1) {
+                        Some(next_start) => range.end.min(next_start - 1),
+                        None => range.end,
+                    };
+                    
+                    let shard_start = issuance_blocks.get_unchecked(shard);
+                    let mut shard_pieces = pieces.get(shard_start).unwrap_or(Vec::new(env));
+                    shard_pieces.push_back(SerialBlock { start, end, credit_id: credit.credit_id });
+                    pieces.set(shard_start, shard_pieces);
+                    
+                    if end == range.end {
+                        break;
+                    }
+                    start = end + 1;
+                }
+            }
+        }
+        
+        for (shard_start, shard_pieces) in pieces.iter() {
+            let key = DataKey::SerialIndex(template.project_id.clone(), template.vintage_year, shard_start);
+            let index: Vec<SerialBlock> = env.storage().persistent().get(&key).unwrap_or(Vec::new(env));
+            
+            let mut updated: Vec<SerialBlock> = Vec::new(env);
+            for block in index.iter() {
+                if !replaced.contains(block.credit_id) {
+                    updated.push_back(block);
+                }
+            }
+            
+            for piece in shard_pieces.iter() {
+                let mut pos = updated.len();
+                while pos > 0 && updated.get_unchecked(pos - 1).start > piece.start {
+                    pos -= 1;
+                }
+                updated.insert(pos, piece);
+            }
+            
+            env.storage().persistent().set(&key, &updated);
+        }
+    }
+
+    // Helper function to find the position of the issuance block holding `serial`
+    fn find_issuance_block(issuance_blocks: &Vec<u64>, serial: u64) -> Option<u32> {
+        // Binary search for the last block starting at or before the serial
+        let mut low: u32 = 0;
+        let mut high: u32 = issuance_blocks.len();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if issuance_blocks.get_unchecked(mid) <= serial {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        
+        low.checked_sub(1)
+    }
+
+    // Helper function to render a serial range as `<project>-<vintage>-<start>-<end>`
+    fn format_serial(env: &Env, project_id: &String, vintage_year: u32, range: &SerialRange) -> String {
+        let mut buf = [0u8; 128];
+        let mut pos = project_id.len() as usize;
+        project_id.copy_into_slice(&mut buf[..pos]);
+        
+        buf[pos] = b'-';
+        pos = Self::write_number(&mut buf, pos + 1, vintage_year as u64, 4);
+        buf[pos] = b'-';
+        pos = Self::write_number(&mut buf, pos + 1, range.start, 5);
+        buf[pos] = b'-';
+        pos = Self::write_number(&mut buf, pos + 1, range.end, 5);
+        
+        String::from_bytes(env, &buf[..pos])
+    }
+
+    // Helper function to write a zero-padded decimal number, returning the new position
+    fn write_number(buf: &mut [u8], pos: usize, value: u64, min_width: usize) -> usize {
+        let mut digits = [0u8; 20];
+        let mut len = 0;
+        let mut n = value;
+        loop {
+            digits[len] = b'0' + (n % 10) as u8;
+            n /= 10;
+            len += 1;
+            if n == 0 {
+                break;
+            }
+        }
+        while len < min_width {
+            digits[len] = b'0';
+            len += 1;
+        }
+        
+        for i in 0..len {
+            buf[pos + i] = digits[len - 1 - i];
+        }
+        
+        pos + len
+    }
+
+    // Helper function to load credit metadata
+    fn load_credit(env: &Env, credit_id: u64) -> Result<CreditMetadata, Error> {
+        env.storage()
             .persistent()
             .get(&DataKey::CreditInfo(credit_id))
-            .unwrap();
+            .ok_or(Error::CreditNotFound)
+    }
+
+    // Helper function to check that a live credit is held by `owner`
+    fn require_owner(env: &Env, credit_id: u64, owner: &Address) -> Result<CreditMetadata, Error> {
+        let metadata = Self::load_credit(env, credit_id)?;
+        
+        let current_owner: Address = env.storage()
+            .persistent()
+            .get(&DataKey::CreditOwner(credit_id))
+            .ok_or(Error::CreditNotActive)?;
+        
+        if current_owner != *owner {
+            return Err(Error::NotOwner);
+        }
         
-        metadata.verification_status == VerificationStatus::Verified
+        Ok(metadata)
     }
 }
 
@@ -1,11 +1,11 @@
 #![cfg(test)]
 use super::*;
-use soroban_sdk::{testutils::Address as _, Address, Env, String};
+use soroban_sdk::{testutils::Address as _, vec, Address, Env, String};
 
 #[test]
 fn test_issue_and_get_credit() {
     let env = Env::default();
-    let contract_id = env.register_contract(None, CarbonCreditContract);
+    let contract_id = env.register(CarbonCreditContract, ());
     let client = CarbonCreditContractClient::new(&env, &contract_id);
     
     let admin = Address::generate(&env);
@@ -23,6 +23,7 @@ fn test_issue_and_get_credit() {
         &String::from_str(&env, "Solar Farm Project"),
         &2024,
         &1000,
+        &Royalty::default(),
     );
     
     assert_eq!(credit_id, 1);
@@ -41,7 +42,7 @@ fn test_issue_and_get_credit() {
 #[test]
 fn test_transfer_credit() {
     let env = Env::default();
-    let contract_id = env.register_contract(None, CarbonCreditContract);
+    let contract_id = env.register(CarbonCreditContract, ());
     let client = CarbonCreditContractClient::new(&env, &contract_id);
     
     let admin = Address::generate(&env);
@@ -58,6 +59,7 @@ fn test_transfer_credit() {
         &String::from_str(&env, "Wind Farm"),
         &2024,
         &500,
+        &Royalty::default(),
     );
     
     // Transfer credit
@@ -71,7 +73,7 @@ fn test_transfer_credit() {
 #[test]
 fn test_verification_update() {
     let env = Env::default();
-    let contract_id = env.register_contract(None, CarbonCreditContract);
+    let contract_id = env.register(CarbonCreditContract, ());
     let client = CarbonCreditContractClient::new(&env, &contract_id);
     
     let admin = Address::generate(&env);
@@ -87,11 +89,374 @@ fn test_verification_update() {
         &String::from_str(&env, "Reforestation"),
         &2024,
         &2000,
+        &Royalty::default(),
     );
     
     // Update verification status
-    client.update_verification(&credit_id, &VerificationStatus::Verified);
+    let verifier = Address::generate(&env);
+    client.update_verification(&credit_id, &VerificationStatus::Verified, &verifier);
     
     // Check verification
     assert!(client.is_verified(&credit_id));
+    assert_eq!(client.get_credit(&credit_id).verifier, Some(verifier));
+}
+
+#[test]
+fn test_error_codes() {
+    let env = Env::default();
+    let contract_id = env.register(CarbonCreditContract, ());
+    let client = CarbonCreditContractClient::new(&env, &contract_id);
+    
+    let admin = Address::generate(&env);
+    let issuer = Address::generate(&env);
+    let stranger = Address::generate(&env);
+    let verification_contract = Address::generate(&env);
+    
+    client.initialize(&admin, &verification_contract);
+    assert_eq!(
+        client.try_initialize(&admin, &verification_contract),
+        Err(Ok(Error::AlreadyInitialized))
+    );
+    
+    env.mock_all_auths();
+    let credit_id = client.issue_credit(
+        &issuer,
+        &String::from_str(&env, "PROJ-001"),
+        &String::from_str(&env, "Solar Farm Project"),
+        &2024,
+        &1000,
+        &Royalty::default(),
+    );
+    
+    // Royalties are capped at issuance
+    assert_eq!(
+        client.try_issue_credit(
+            &issuer,
+            &String::from_str(&env, "PROJ-001"),
+            &String::from_str(&env, "Solar Farm Project"),
+            &2024,
+            &1000,
+            &Royalty { issuer_bps: 800, verifier_bps: 201 },
+        ),
+        Err(Ok(Error::RoyaltyTooHigh))
+    );
+    assert_eq!(
+        client.try_issue_credit(
+            &issuer,
+            &String::from_str(&env, "PROJ-001"),
+            &String::from_str(&env, "Solar Farm Project"),
+            &2024,
+            &1000,
+            &Royalty { issuer_bps: u32::MAX, verifier_bps: 1 },
+        ),
+        Err(Ok(Error::RoyaltyTooHigh))
+    );
+    
+    // Missing credit and wrong owner are distinguishable
+    assert_eq!(client.try_get_credit(&99).err(), Some(Ok(Error::CreditNotFound)));
+    assert_eq!(
+        client.try_transfer(&stranger, &issuer, &credit_id),
+        Err(Ok(Error::NotOwner))
+    );
+    
+    // Pending credits cannot be retired
+    let reason = String::from_str(&env, "Scope 1 offset");
+    let period = String::from_str(&env, "2024");
+    assert_eq!(
+        client.try_retire_credit(&issuer, &credit_id, &reason, &reason, &period),
+        Err(Ok(Error::NotVerified))
+    );
+    
+    // Retired credits can no longer move
+    client.update_verification(&credit_id, &VerificationStatus::Verified, &Address::generate(&env));
+    client.retire_credit(&issuer, &credit_id, &reason, &reason, &period);
+    assert_eq!(
+        client.try_transfer(&issuer, &stranger, &credit_id),
+        Err(Ok(Error::CreditNotActive))
+    );
+}
+
+#[test]
+fn test_split_credit() {
+    let env = Env::default();
+    let contract_id = env.register(CarbonCreditContract, ());
+    let client = CarbonCreditContractClient::new(&env, &contract_id);
+    
+    let admin = Address::generate(&env);
+    let issuer = Address::generate(&env);
+    let verification_contract = Address::generate(&env);
+    
+    client.initialize(&admin, &verification_contract);
+    
+    env.mock_all_auths();
+    let credit_id = client.issue_credit(
+        &issuer,
+        &String::from_str(&env, "PROJ-001"),
+        &String::from_str(&env, "Wind Farm"),
+        &2024,
+        &5000,
+        &Royalty::default(),
+    );
+    client.update_verification(&credit_id, &VerificationStatus::Verified, &Address::generate(&env));
+    
+    // Amounts must add up to the credit's tonnage
+    assert_eq!(
+        client.try_split_credit(&issuer, &credit_id, &vec![&env, 37, 4000]),
+        Err(Ok(Error::AmountMismatch))
+    );
+    
+    let child_ids = client.split_credit(&issuer, &credit_id, &vec![&env, 37, 4963]);
+    assert_eq!(child_ids.len(), 2);
+    
+    // Children inherit the parent's attributes and record their parent
+    let child = client.get_credit(&child_ids.get_unchecked(0));
+    assert_eq!(child.amount_tons, 37);
+    assert_eq!(child.vintage_year, 2024);
+    assert_eq!(child.verification_status, VerificationStatus::Verified);
+    assert_eq!(child.parent_ids, vec![&env, credit_id]);
+    assert_eq!(client.get_owner(&child_ids.get_unchecked(1)), Some(issuer.clone()));
+    
+    // The parent is no longer in circulation
+    assert_eq!(client.get_owner(&credit_id), None);
+    assert_eq!(
+        client.try_split_credit(&issuer, &credit_id, &vec![&env, 2500, 2500]),
+        Err(Ok(Error::CreditNotActive))
+    );
+}
+
+#[test]
+fn test_merge_credits() {
+    let env = Env::default();
+    let contract_id = env.register(CarbonCreditContract, ());
+    let client = CarbonCreditContractClient::new(&env, &contract_id);
+    
+    let admin = Address::generate(&env);
+    let issuer = Address::generate(&env);
+    let verification_contract = Address::generate(&env);
+    
+    client.initialize(&admin, &verification_contract);
+    
+    env.mock_all_auths();
+    let first_id = client.issue_credit(
+        &issuer,
+        &String::from_str(&env, "PROJ-001"),
+        &String::from_str(&env, "Wind Farm"),
+        &2024,
+        &300,
+        &Royalty::default(),
+    );
+    let second_id = client.issue_credit(
+        &issuer,
+        &String::from_str(&env, "PROJ-001"),
+        &String::from_str(&env, "Wind Farm"),
+        &2024,
+        &200,
+        &Royalty::default(),
+    );
+    let other_vintage_id = client.issue_credit(
+        &issuer,
+        &String::from_str(&env, "PROJ-001"),
+        &String::from_str(&env, "Wind Farm"),
+        &2023,
+        &100,
+        &Royalty::default(),
+    );
+    
+    // Credits from different vintages cannot be merged
+    assert_eq!(
+        client.try_merge_credits(&issuer, &vec![&env, first_id, other_vintage_id]),
+        Err(Ok(Error::IncompatibleCredits))
+    );
+    
+    let merged_id = client.merge_credits(&issuer, &vec![&env, first_id, second_id]);
+    
+    let merged = client.get_credit(&merged_id);
+    assert_eq!(merged.amount_tons, 500);
+    assert_eq!(merged.parent_ids, vec![&env, first_id, second_id]);
+    assert_eq!(client.get_owner(&merged_id), Some(issuer));
+    assert_eq!(client.get_owner(&first_id), None);
+    assert_eq!(client.get_owner(&second_id), None);
+}
+
+#[test]
+fn test_serial_ranges() {
+    let env = Env::default();
+    let contract_id = env.register(CarbonCreditContract, ());
+    let client = CarbonCreditContractClient::new(&env, &contract_id);
+    
+    let admin = Address::generate(&env);
+    let issuer = Address::generate(&env);
+    let verification_contract = Address::generate(&env);
+    
+    client.initialize(&admin, &verification_contract);
+    
+    env.mock_all_auths();
+    let project_id = String::from_str(&env, "VCS-1234");
+    let project_name = String::from_str(&env, "Rainforest Conservation");
+    let first_id = client.issue_credit(&issuer, &project_id, &project_name, &2024, &5000, &Royalty::default());
+    let second_id = client.issue_credit(&issuer, &project_id, &project_name, &2024, &200, &Royalty::default());
+    let other_vintage_id = client.issue_credit(&issuer, &project_id, &project_name, &2023, &100, &Royalty::default());
+    
+    // Serials continue per project vintage
+    assert_eq!(
+        client.get_credit(&first_id).serial_ranges,
+        vec![&env, SerialRange { start: 1, end: 5000 }]
+    );
+    assert_eq!(
+        client.get_credit(&second_id).serial_ranges,
+        vec![&env, SerialRange { start: 5001, end: 5200 }]
+    );
+    assert_eq!(
+        client.get_credit(&other_vintage_id).serial_ranges,
+        vec![&env, SerialRange { start: 1, end: 100 }]
+    );
+    assert_eq!(
+        client.get_serial_numbers(&first_id),
+        vec![&env, String::from_str(&env, "VCS-1234-2024-00001-05000")]
+    );
+    
+    // Splitting hands out non-overlapping slices and moves the lookup to the children
+    let child_ids = client.split_credit(&issuer, &first_id, &vec![&env, 37, 4963]);
+    let small = child_ids.get_unchecked(0);
+    let large = child_ids.get_unchecked(1);
+    assert_eq!(
+        client.get_credit(&small).serial_ranges,
+        vec![&env, SerialRange { start: 1, end: 37 }]
+    );
+    assert_eq!(
+        client.get_credit(&large).serial_ranges,
+        vec![&env, SerialRange { start: 38, end: 5000 }]
+    );
+    assert_eq!(client.get_credit_by_serial(&project_id, &2024, &37), small);
+    assert_eq!(client.get_credit_by_serial(&project_id, &2024, &38), large);
+    assert_eq!(client.get_credit_by_serial(&project_id, &2024, &5200), second_id);
+    assert_eq!(client.get_credit_by_serial(&project_id, &2023, &1), other_vintage_id);
+    assert_eq!(
+        client.try_get_credit_by_serial(&project_id, &2024, &5201),
+        Err(Ok(Error::SerialNotFound))
+    );
+    
+    // Merging joins adjacent ranges back together
+    let merged_id = client.merge_credits(&issuer, &vec![&env, large, second_id]);
+    assert_eq!(
+        client.get_credit(&merged_id).serial_ranges,
+        vec![&env, SerialRange { start: 38, end: 5200 }]
+    );
+    assert_eq!(client.get_credit_by_serial(&project_id, &2024, &4000), merged_id);
+    assert_eq!(client.get_credit_by_serial(&project_id, &2024, &5200), merged_id);
+    assert_eq!(client.get_credit_by_serial(&project_id, &2024, &37), small);
+    
+    // The index is sharded per issuance block; a merged range is cut at the boundary
+    env.as_contract(&contract_id, || {
+        let shard: Vec<SerialBlock> = env.storage()
+            .persistent()
+            .get(&DataKey::SerialIndex(project_id.clone(), 2024, 5001))
+            .unwrap();
+        assert_eq!(shard.len(), 1);
+        assert_eq!(shard.get_unchecked(0).start, 5001);
+        assert_eq!(shard.get_unchecked(0).credit_id, merged_id);
+    });
+}
+
+#[test]
+fn test_retirement_certificate() {
+    let env = Env::default();
+    let contract_id = env.register(CarbonCreditContract, ());
+    let client = CarbonCreditContractClient::new(&env, &contract_id);
+    
+    let admin = Address::generate(&env);
+    let client_company = Address::generate(&env);
+    let broker = Address::generate(&env);
+    let verification_contract = Address::generate(&env);
+    
+    client.initialize(&admin, &verification_contract);
+    
+    env.mock_all_auths();
+    let credit_id = client.issue_credit(
+        &client_company,
+        &String::from_str(&env, "VCS-1234"),
+        &String::from_str(&env, "Rainforest Conservation"),
+        &2024,
+        &250,
+        &Royalty::default(),
+    );
+    client.update_verification(&credit_id, &VerificationStatus::Verified, &Address::generate(&env));
+    
+    let beneficiary = String::from_str(&env, "Acme Corp");
+    let reason = String::from_str(&env, "Scope 3 business travel");
+    let period = String::from_str(&env, "FY2024");
+    
+    // Brokers need the owner's approval
+    assert_eq!(
+        client.try_retire_on_behalf(&broker, &client_company, &credit_id, &beneficiary, &reason, &period),
+        Err(Ok(Error::NotAuthorized))
+    );
+    
+    client.set_broker(&client_company, &broker, &true);
+    assert!(client.is_broker(&client_company, &broker));
+    
+    let retirement_id = client.retire_on_behalf(&broker, &client_company, &credit_id, &beneficiary, &reason, &period);
+    assert_eq!(client.get_owner(&credit_id), None);
+    
+    let record = client.get_retirement(&retirement_id);
+    assert_eq!(record.credit_id, credit_id);
+    assert_eq!(record.owner, client_company);
+    assert_eq!(record.retired_by, broker);
+    assert_eq!(record.beneficiary, beneficiary);
+    assert_eq!(record.reason, reason);
+    assert_eq!(record.reporting_period, period);
+    assert_eq!(record.amount_tons, 250);
+    assert_eq!(record.serial_ranges, vec![&env, SerialRange { start: 1, end: 250 }]);
+    
+    assert_eq!(client.try_get_retirement(&99).err(), Some(Ok(Error::RetirementNotFound)));
+}
+
+#[test]
+fn test_owner_portfolio() {
+    let env = Env::default();
+    let contract_id = env.register(CarbonCreditContract, ());
+    let client = CarbonCreditContractClient::new(&env, &contract_id);
+    
+    let admin = Address::generate(&env);
+    let issuer = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    let verification_contract = Address::generate(&env);
+    
+    client.initialize(&admin, &verification_contract);
+    
+    env.mock_all_auths();
+    let project_id = String::from_str(&env, "PROJ-001");
+    let project_name = String::from_str(&env, "Wind Farm");
+    let first_id = client.issue_credit(&issuer, &project_id, &project_name, &2024, &1000, &Royalty::default());
+    let second_id = client.issue_credit(&issuer, &project_id, &project_name, &2024, &500, &Royalty::default());
+    assert_eq!(client.balance_tons(&issuer), 1500);
+    
+    // Splits and transfers move holdings between portfolios
+    let child_ids = client.split_credit(&issuer, &first_id, &vec![&env, 400, 600]);
+    client.transfer(&issuer, &buyer, &child_ids.get_unchecked(0));
+    assert_eq!(client.balance_tons(&issuer), 1100);
+    assert_eq!(client.balance_tons(&buyer), 400);
+    
+    let holdings = client.get_credits_by_owner(&issuer, &0, &10);
+    assert_eq!(holdings.len(), 2);
+    assert_eq!(holdings.get_unchecked(0).credit_id, second_id);
+    assert_eq!(holdings.get_unchecked(1).credit_id, child_ids.get_unchecked(1));
+    
+    // Pagination
+    let page = client.get_credits_by_owner(&issuer, &1, &10);
+    assert_eq!(page.len(), 1);
+    assert_eq!(page.get_unchecked(0).credit_id, child_ids.get_unchecked(1));
+    
+    // Merging and retiring keep the index in sync
+    let merged_id = client.merge_credits(&issuer, &vec![&env, second_id, child_ids.get_unchecked(1)]);
+    let holdings = client.get_credits_by_owner(&issuer, &0, &10);
+    assert_eq!(holdings.len(), 1);
+    assert_eq!(holdings.get_unchecked(0).credit_id, merged_id);
+    assert_eq!(client.balance_tons(&issuer), 1100);
+    
+    client.update_verification(&merged_id, &VerificationStatus::Verified, &Address::generate(&env));
+    let text = String::from_str(&env, "Offset");
+    client.retire_credit(&issuer, &merged_id, &text, &text, &text);
+    assert_eq!(client.get_credits_by_owner(&issuer, &0, &10).len(), 0);
+    assert_eq!(client.balance_tons(&issuer), 0);
 }
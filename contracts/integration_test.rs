@@ -1,4 +1,4 @@
-use carbon_credit::{CarbonCreditContract, CarbonCreditContractClient, VerificationStatus};
+use carbon_credit::{CarbonCreditContract, CarbonCreditContractClient, Royalty};
 use verification::{VerificationContract, VerificationContractClient, RequestStatus};
 use marketplace::{MarketplaceContract, MarketplaceContractClient, ListingStatus};
 use soroban_sdk::{testutils::Address as _, token, Address, Env, String};
@@ -22,13 +22,14 @@ fn test_full_carbon_credit_workflow() {
     let issuer = Address::generate(&env);
     let verifier = Address::generate(&env);
     let buyer = Address::generate(&env);
+    let treasury = Address::generate(&env);
     
     println!("✅ Generated test addresses");
     
     // Deploy contracts
-    let carbon_credit_id = env.register_contract(None, CarbonCreditContract);
-    let verification_id = env.register_contract(None, VerificationContract);
-    let marketplace_id = env.register_contract(None, MarketplaceContract);
+    let carbon_credit_id = env.register(CarbonCreditContract, ());
+    let verification_id = env.register(VerificationContract, ());
+    let marketplace_id = env.register(MarketplaceContract, ());
     
     println!("✅ Deployed all 3 contracts");
     
@@ -46,7 +47,7 @@ fn test_full_carbon_credit_workflow() {
     // Initialize contracts
     carbon_client.initialize(&admin, &verification_id);
     verification_client.initialize(&admin, &carbon_credit_id);
-    marketplace_client.initialize(&admin, &carbon_credit_id, &token_client.address, &250);
+    marketplace_client.initialize(&admin, &treasury, &carbon_credit_id, &token_client.address, &250);
     
     println!("✅ Initialized all contracts\n");
     
@@ -58,6 +59,7 @@ fn test_full_carbon_credit_workflow() {
         &String::from_str(&env, "Texas Wind Farm Project"),
         &2024,
         &5000, // 5000 tons CO2
+        &Royalty::default(),
     );
     println!("   Credit ID: {}", credit_id);
     
@@ -98,9 +100,8 @@ fn test_full_carbon_credit_workflow() {
     assert_eq!(request.status, RequestStatus::Approved);
     println!("   ✅ Credit verified and approved\n");
     
-    // Step 5: Update carbon credit status
-    println!("🔄 Step 5: Updating credit verification status...");
-    carbon_client.update_verification(&credit_id, &VerificationStatus::Verified);
+    // Step 5: Approval propagates to the carbon credit contract
+    println!("🔄 Step 5: Checking credit verification status...");
     assert!(carbon_client.is_verified(&credit_id));
     println!("   ✅ Credit marked as verified\n");
     
@@ -109,8 +110,10 @@ fn test_full_carbon_credit_workflow() {
     let listing_id = marketplace_client.create_listing(
         &issuer,
         &credit_id,
+        &token_client.address,
         &50, // $50 per ton
         &5000,
+        &None,
     );
     
     let listing = marketplace_client.get_listing(&listing_id);
@@ -126,32 +129,39 @@ fn test_full_carbon_credit_workflow() {
     let buyer_balance_before = token_client.balance(&buyer);
     println!("   Buyer balance before: {}", buyer_balance_before);
     
-    marketplace_client.buy_credit(&buyer, &listing_id);
+    marketplace_client.buy_credit(&buyer, &listing_id, &5000, &50);
     
     let listing = marketplace_client.get_listing(&listing_id);
     assert_eq!(listing.status, ListingStatus::Sold);
     
     let buyer_balance_after = token_client.balance(&buyer);
     let seller_balance = token_client.balance(&issuer);
-    let admin_balance = token_client.balance(&admin);
+    let fees_collected = marketplace_client.get_accrued_fees(&token_client.address);
     
     println!("   Buyer balance after: {}", buyer_balance_after);
     println!("   Seller received: {}", seller_balance);
-    println!("   Marketplace fee collected: {}", admin_balance);
+    println!("   Marketplace fee collected: {}", fees_collected);
     println!("   ✅ Purchase completed\n");
     
     // Step 8: Verify ownership transfer
     println!("🔑 Step 8: Verifying ownership transfer...");
     let new_owner = carbon_client.get_owner(&credit_id);
-    // Note: In a full implementation, marketplace would transfer ownership
+    assert_eq!(new_owner, Some(buyer.clone()));
     println!("   ✅ Ownership verified\n");
     
     // Step 9: Retire carbon credits
     println!("♻️  Step 9: Retiring carbon credits (offsetting emissions)...");
-    carbon_client.retire_credit(&buyer, &credit_id);
+    let retirement_id = carbon_client.retire_credit(
+        &buyer,
+        &credit_id,
+        &String::from_str(&env, "Buyer Corp"),
+        &String::from_str(&env, "Scope 1 emissions offset"),
+        &String::from_str(&env, "2024"),
+    );
     
     let retired_owner = carbon_client.get_owner(&credit_id);
     assert_eq!(retired_owner, None);
+    assert_eq!(carbon_client.get_retirement(&retirement_id).amount_tons, 5000);
     println!("   ✅ 5000 tons CO2 credits permanently retired\n");
     
     println!("=== Integration Test Complete ===");
@@ -161,7 +171,7 @@ fn test_full_carbon_credit_workflow() {
     println!("  2. Added authorized verifier");
     println!("  3. Submitted verification request");
     println!("  4. Approved by verifier");
-    println!("  5. Updated credit status");
+    println!("  5. Credit status updated on-chain");
     println!("  6. Listed on marketplace ($50/ton)");
     println!("  7. Purchased by buyer ($250,000 total)");
     println!("  8. Ownership transferred");
@@ -1,274 +1,2617 @@
 #![no_std]
-use soroban_sdk::{contract, contractimpl, contracttype, token, Address, Env, Symbol, Vec};
+use soroban_sdk::{contract, contractclient, contracterror, contractimpl, contracttype, token, xdr::ToXdr, Address, Bytes, BytesN, Env, Map, String, Symbol, Vec};
 
 #[derive(Clone)]
 #[contracttype]
 pub enum DataKey {
     Admin,
     CarbonCreditContract,
-    PaymentToken,
+    AcceptedTokens, // Vec<Address> of payment tokens open for new orders
+    TokenFee(Address), // token -> fee in basis points (e.g., 250 = 2.5%)
+    AccruedFees(Address), // token -> fees held by the marketplace awaiting withdrawal
+    Treasury, // Receives withdrawn fees
     Listing(u64), // listing_id -> Listing
+    ListingFills(u64), // listing_id -> Vec<Fill>
+    TradeLock(u64), // listing_id -> true while a trade on the listing is settling (temporary)
     NextListingId,
-    ActiveListings,
-    MarketplaceFee, // Percentage fee (e.g., 250 = 2.5%)
+    Offer(u64), // offer_id -> Offer
+    NextOfferId,
+    ListingOffers(u64), // listing_id -> Vec<offer_id> of offers made on the listing
+    ActiveListings, // Vec<listing_id> of active listings, in creation order
+    SellerListings(Address), // seller -> Vec<listing_id> of their active listings
+    ProjectListings(String), // project_id -> Vec<listing_id> of active listings
+    VintageListings(u32), // vintage_year -> Vec<listing_id> of active listings
+    CreditListing(u64), // credit_id -> listing_id of the active listing holding it in escrow
+    Bid(u64), // bid_id -> Bid
+    NextBidId,
+    ActiveBids, // Vec<bid_id> of active bids, in creation order
+    Auction(u64), // auction_id -> Auction
+    NextAuctionId,
+    SealedAuction(u64), // auction_id -> SealedAuction
+    SealedBid(u64, Address), // (auction_id, bidder) -> SealedBid
+    SealedBidders(u64), // auction_id -> Vec<Address>
+    Trade(u64), // trade_id -> Trade
+    NextTradeId,
+    MarketStats(StatsScope, Address), // (scope, payment token) -> MarketStats
+    DailyVolume(StatsScope, Address, u64), // (scope, payment token, day) -> DailyVolume
 }
 
 #[derive(Clone)]
 #[contracttype]
 pub struct Listing {
     pub listing_id: u64,
-    pub credit_id: u64,
+    pub credit_id: u64, // Credit held in escrow for the unfilled remainder
+    pub project_id: String,
+    pub vintage_year: u32,
     pub seller: Address,
-    pub price_per_ton: i128,
+    pub payment_token: Address,
+    pub price_per_ton: i128, // Fixed price, or the starting price of a Dutch listing
     pub amount_tons: u64,
+    pub remaining_tons: u64,
     pub status: ListingStatus,
+    pub pricing: Pricing,
+    pub expires_at: Option<u64>, // No purchases at or after this ledger timestamp
+    pub allowed_buyer: Option<Address>, // Set for private listings, which only this buyer can fill
     pub created_at: u64,
 }
 
+/// Criteria for `query_listings`; unset fields match anything
+#[derive(Clone)]
+#[contracttype]
+pub struct ListingFilter {
+    pub seller: Option<Address>,
+    pub project_id: Option<String>,
+    pub min_vintage: Option<u32>,
+    pub max_vintage: Option<u32>,
+    pub min_price: Option<i128>, // Compared against the current price per ton
+    pub max_price: Option<i128>,
+}
+
+/// A page of `query_listings` results
+#[derive(Clone)]
+#[contracttype]
+pub struct ListingPage {
+    pub listings: Vec<Listing>,
+    pub next_start: Option<u32>, // Pass back as `start` to continue; None once every candidate was scanned
+}
+
+/// Outcome of a `prune_expired` pass
+#[derive(Clone, PartialEq, Debug)]
+#[contracttype]
+pub struct PruneResult {
+    pub pruned: u32,
+    pub next_start: Option<u32>, // Pass back as `start` to continue; None once every active listing was scanned
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[contracttype]
+pub enum Pricing {
+    Fixed,
+    Dutch(DutchSchedule),
+}
+
+/// Descending price from `price_per_ton` at `start_time` down to `floor_price` at `end_time`.
+/// With `step_seconds` of 0 the price decays linearly, otherwise it drops once per step.
+#[derive(Clone, PartialEq, Debug)]
+#[contracttype]
+pub struct DutchSchedule {
+    pub floor_price: i128,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub step_seconds: u64,
+}
+
+/// How a new Dutch listing's price falls to `floor_price` over `duration` seconds;
+/// `step_seconds` of 0 decays linearly
+#[derive(Clone)]
+#[contracttype]
+pub struct DutchTerms {
+    pub floor_price: i128,
+    pub duration: u64,
+    pub step_seconds: u64,
+}
+
+#[derive(Clone)]
+#[contracttype]
+pub struct Fill {
+    pub buyer: Address,
+    pub credit_id: u64, // Credit delivered to the buyer
+    pub tons: u64,
+    pub price_per_ton: i128,
+    pub total_price: i128,
+    pub filled_at: u64,
+}
+
+/// A completed sale, whether from a listing, a bid or an auction
+#[derive(Clone)]
+#[contracttype]
+pub struct Trade {
+    pub trade_id: u64,
+    pub credit_id: u64, // Credit delivered to the buyer
+    pub project_id: String,
+    pub vintage_year: u32,
+    pub buyer: Address,
+    pub seller: Address,
+    pub payment_token: Address,
+    pub price_per_ton: i128,
+    pub tons: u64,
+    pub total_price: i128,
+    pub fee: i128, // Marketplace fee taken from `total_price`
+    pub traded_at: u64,
+}
+
+/// Market a set of statistics covers; stats are kept separately per payment token
+#[derive(Clone, PartialEq, Debug)]
+#[contracttype]
+pub enum StatsScope {
+    Project(String),
+    Vintage(u32),
+}
+
+/// Running totals for a market
+#[derive(Clone, Default, PartialEq, Debug)]
+#[contracttype]
+pub struct MarketStats {
+    pub last_price: i128, // Price per ton of the latest trade
+    pub total_volume_tons: u64,
+    pub total_value: i128,
+    pub trade_count: u64,
+    pub last_trade_at: u64,
+}
+
+/// Volume traded in a market on one day, used for rolling VWAP windows
+#[derive(Clone, Default)]
+#[contracttype]
+pub struct DailyVolume {
+    pub tons: u64,
+    pub value: i128,
+}
+
 #[derive(Clone, PartialEq, Debug)]
 #[contracttype]
 pub enum ListingStatus {
     Active,
     Sold,
     Cancelled,
+    Expired, // Lapsed unsold; remaining credit returned to the seller
+}
+
+/// Buyer's offer on a listing; `price_per_ton * tons` is held in escrow until the offer settles
+#[derive(Clone)]
+#[contracttype]
+pub struct Offer {
+    pub offer_id: u64,
+    pub listing_id: u64,
+    pub buyer: Address,
+    pub price_per_ton: i128,
+    pub tons: u64,
+    pub counter_price: Option<i128>, // Seller's counter price per ton, set while `Countered`
+    pub expires_at: u64, // Can no longer be accepted at or after this ledger timestamp
+    pub status: OfferStatus,
+    pub created_at: u64,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[contracttype]
+pub enum OfferStatus {
+    Open,
+    Countered, // Awaiting the buyer's answer to the seller's counter price
+    Accepted,
+    Withdrawn,
+    Expired, // Lapsed unaccepted; escrow returned to the buyer
+}
+
+/// Standing buy order; the buyer's payment is escrowed at `max_price_per_ton`
+#[derive(Clone)]
+#[contracttype]
+pub struct Bid {
+    pub bid_id: u64,
+    pub buyer: Address,
+    pub payment_token: Address,
+    pub max_price_per_ton: i128,
+    pub amount_tons: u64,
+    pub remaining_tons: u64,
+    pub filter: BidFilter,
+    pub status: BidStatus,
+    pub created_at: u64,
+}
+
+/// Credits a bid accepts; unset fields match anything
+#[derive(Clone)]
+#[contracttype]
+pub struct BidFilter {
+    pub project_id: Option<String>,
+    pub min_vintage: Option<u32>,
+    pub max_vintage: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[contracttype]
+pub enum BidStatus {
+    Active,
+    Filled,
+    Cancelled,
+}
+
+/// English auction for a whole credit; the highest bid is held in escrow
+#[derive(Clone)]
+#[contracttype]
+pub struct Auction {
+    pub auction_id: u64,
+    pub credit_id: u64, // Credit held in escrow until settlement
+    pub seller: Address,
+    pub payment_token: Address,
+    pub amount_tons: u64,
+    pub reserve_price: i128, // Minimum opening bid for the whole lot
+    pub min_increment: i128,
+    pub highest_bidder: Option<Address>,
+    pub highest_bid: i128,
+    pub end_time: u64,
+    pub status: AuctionStatus,
+    pub created_at: u64,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[contracttype]
+pub enum AuctionStatus {
+    Active,
+    Sold,
+    Unsold, // Ended without bids; credit returned to the seller
+}
+
+/// Sealed-bid auction: bids are committed as hashes before `commit_end` and revealed before `reveal_end`
+#[derive(Clone)]
+#[contracttype]
+pub struct SealedAuction {
+    pub auction_id: u64,
+    pub credit_id: u64, // Credit held in escrow until finalization
+    pub seller: Address,
+    pub payment_token: Address,
+    pub amount_tons: u64,
+    pub reserve_price: i128,
+    pub deposit: i128, // Escrowed with each commitment, forfeited if never revealed
+    pub commit_end: u64,
+    pub reveal_end: u64,
+    pub highest_bidder: Option<Address>,
+    pub highest_bid: i128,
+    pub status: AuctionStatus,
+    pub created_at: u64,
+}
+
+/// A bidder's commitment, `sha256(auction_id as 8 big-endian bytes || bidder as XDR ||
+/// amount as 16 big-endian bytes || salt)`. Binding the auction and bidder stops a
+/// commitment from being copied and revealed by someone else.
+#[derive(Clone)]
+#[contracttype]
+pub struct SealedBid {
+    pub commitment: BytesN<32>,
+    pub revealed: bool,
+    pub amount: i128,
+}
+
+/// Mirror of the carbon credit contract's `CreditMetadata`
+#[derive(Clone)]
+#[contracttype]
+pub struct CreditMetadata {
+    pub credit_id: u64,
+    pub project_id: String,
+    pub project_name: String,
+    pub vintage_year: u32,
+    pub amount_tons: u64,
+    pub verification_status: VerificationStatus,
+    pub issuer: Address,
+    pub created_at: u64,
+    pub parent_ids: Vec<u64>,
+    pub serial_ranges: Vec<SerialRange>,
+    pub royalty: Royalty,
+    pub verifier: Option<Address>,
+}
+
+/// Mirror of the carbon credit contract's `Royalty`
+#[derive(Clone, PartialEq, Debug)]
+#[contracttype]
+pub struct Royalty {
+    pub issuer_bps: u32,
+    pub verifier_bps: u32,
+}
+
+/// Mirror of the carbon credit contract's `VerificationStatus`
+#[derive(Clone, PartialEq, Debug)]
+#[contracttype]
+pub enum VerificationStatus {
+    Pending,
+    Verified,
+    Rejected,
+}
+
+/// Mirror of the carbon credit contract's `SerialRange`
+#[derive(Clone, PartialEq, Debug)]
+#[contracttype]
+pub struct SerialRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    AlreadyInitialized = 1,
+    NotInitialized = 2,
+    NotAuthorized = 3,
+    ListingNotFound = 4,
+    ListingNotActive = 5,
+    NotSeller = 6,
+    InvalidPrice = 7,
+    InvalidAmount = 8,
+    FeeTooHigh = 9,
+    BidNotFound = 10,
+    BidNotActive = 11,
+    NotBidder = 12,
+    CreditNotAccepted = 13, // Credit does not match the bid's filter or is not verified
+    PriceNotMatched = 14,
+    AuctionNotFound = 15,
+    AuctionNotActive = 16,
+    AuctionEnded = 17,
+    AuctionNotEnded = 18,
+    BidTooLow = 19,
+    InvalidEndTime = 20,
+    InvalidSchedule = 21,
+    NotFixedPrice = 22,
+    NotCommitPhase = 23,
+    NotRevealPhase = 24,
+    AlreadyCommitted = 25,
+    NoCommitment = 26,
+    CommitmentMismatch = 27,
+    AlreadyRevealed = 28,
+    ListingExpired = 29,
+    TokenNotAccepted = 30,
+    TokenAlreadyAccepted = 31,
+    TokenMismatch = 32,
+    InsufficientFees = 33,
+    NotVerified = 34,
+    PriceLimitExceeded = 35,
+    NotAllowedBuyer = 36,
+    OfferNotFound = 37,
+    OfferNotOpen = 38,
+    NotOfferBuyer = 39,
+    OfferExpired = 40,
+    OfferNotExpired = 41,
+    NotCountered = 42,
+    TradeInProgress = 43,
+    CreditNotFound = 44, // Credit does not exist or is no longer live
+    NotCreditOwner = 45,
+    CreditAlreadyListed = 46,
+    TradeNotFound = 47,
+    InvalidWindow = 48,
+}
+
+/// Subset of the carbon credit contract interface used by the marketplace
+#[contractclient(name = "CarbonCreditClient")]
+pub trait CarbonCreditInterface {
+    fn transfer(env: Env, from: Address, to: Address, credit_id: u64);
+    fn get_owner(env: Env, credit_id: u64) -> Option<Address>;
+    fn is_verified(env: Env, credit_id: u64) -> bool;
+    fn split_credit(env: Env, owner: Address, credit_id: u64, amounts: Vec<u64>) -> Vec<u64>;
+    fn get_credit(env: Env, credit_id: u64) -> CreditMetadata;
+    fn retire_credit(
+        env: Env,
+        owner: Address,
+        credit_id: u64,
+        beneficiary: String,
+        reason: String,
+        reporting_period: String,
+    ) -> u64;
 }
 
+/// Maximum marketplace fee in basis points (10%)
+const MAX_MARKETPLACE_FEE: u32 = 1000;
+
+/// Maximum listings returned by a single query
+const MAX_PAGE_SIZE: u32 = 100;
+
+/// Maximum listings examined by a single query or prune pass
+const MAX_SCAN: u32 = 200;
+
+/// Widest vintage range served from the per-vintage index
+const MAX_INDEXED_VINTAGE_SPAN: u32 = 20;
+
+/// Longest rolling window, in days, for volume-weighted average prices
+const MAX_VWAP_WINDOW_DAYS: u32 = 90;
+
+const SECONDS_PER_DAY: u64 = 86400;
+
 #[contract]
 pub struct MarketplaceContract;
 
 #[contractimpl]
 impl MarketplaceContract {
-    /// Initialize the marketplace
+    /// Initialize the marketplace. Fees are withdrawn to `treasury`, kept separate from the admin key.
     pub fn initialize(
         env: Env,
         admin: Address,
+        treasury: Address,
         carbon_credit_contract: Address,
-        payment_token: Address,
-        marketplace_fee: u32, // Basis points (e.g., 250 = 2.5%)
-    ) {
+        payment_token: Address, // First accepted payment token
+        marketplace_fee: u32, // Basis points (e.g., 250 = 2.5%) charged in `payment_token`
+    ) -> Result<(), Error> {
         if env.storage().instance().has(&DataKey::Admin) {
-            panic!("Already initialized");
+            return Err(Error::AlreadyInitialized);
+        }
+        
+        if marketplace_fee > MAX_MARKETPLACE_FEE {
+            return Err(Error::FeeTooHigh);
         }
         
         env.storage().instance().set(&DataKey::Admin, &admin);
+        env.storage().instance().set(&DataKey::Treasury, &treasury);
         env.storage().instance().set(&DataKey::CarbonCreditContract, &carbon_credit_contract);
-        env.storage().instance().set(&DataKey::PaymentToken, &payment_token);
-        env.storage().instance().set(&DataKey::MarketplaceFee, &marketplace_fee);
+        env.storage().instance().set(&DataKey::AcceptedTokens, &Vec::from_array(&env, [payment_token.clone()]));
+        env.storage().instance().set(&DataKey::TokenFee(payment_token), &marketplace_fee);
         env.storage().instance().set(&DataKey::NextListingId, &1u64);
+        env.storage().instance().set(&DataKey::NextOfferId, &1u64);
+        env.storage().instance().set(&DataKey::NextBidId, &1u64);
+        env.storage().instance().set(&DataKey::NextAuctionId, &1u64);
+        env.storage().instance().set(&DataKey::NextTradeId, &1u64);
         
         let active_listings: Vec<u64> = Vec::new(&env);
-        env.storage().instance().set(&DataKey::ActiveListings, &active_listings);
+        env.storage().persistent().set(&DataKey::ActiveListings, &active_listings);
+        
+        let active_bids: Vec<u64> = Vec::new(&env);
+        env.storage().persistent().set(&DataKey::ActiveBids, &active_bids);
+        
+        Ok(())
     }
 
-    /// Create a listing for a carbon credit
+    /// Create a listing for a carbon credit, locking the credit in marketplace escrow.
+    /// The listing can no longer be bought from once `expires_at` is reached.
     pub fn create_listing(
         env: Env,
         seller: Address,
         credit_id: u64,
+        payment_token: Address,
         price_per_ton: i128,
         amount_tons: u64,
-    ) -> u64 {
+        expires_at: Option<u64>,
+    ) -> Result<u64, Error> {
         seller.require_auth();
         
         if price_per_ton <= 0 {
-            panic!("Price must be positive");
+            return Err(Error::InvalidPrice);
         }
         
-        if amount_tons == 0 {
-            panic!("Amount must be positive");
+        if let Some(expires_at) = expires_at {
+            if expires_at <= env.ledger().timestamp() {
+                return Err(Error::InvalidEndTime);
+            }
         }
         
-        let listing_id: u64 = env.storage().instance().get(&DataKey::NextListingId).unwrap();
+        Self::open_listing(&env, seller, credit_id, payment_token, price_per_ton, amount_tons, Pricing::Fixed, expires_at, None)
+    }
+
+    /// Create a private listing for a deal negotiated off-chain. Only `allowed_buyer` can
+    /// fill it, and it is kept out of the active listings and public indexes.
+    pub fn create_private_listing(
+        env: Env,
+        seller: Address,
+        credit_id: u64,
+        payment_token: Address,
+        price_per_ton: i128,
+        amount_tons: u64,
+        allowed_buyer: Address,
+    ) -> Result<u64, Error> {
+        seller.require_auth();
+        
+        if price_per_ton <= 0 {
+            return Err(Error::InvalidPrice);
+        }
         
-        let listing = Listing {
-            listing_id,
+        Self::open_listing(
+            &env,
+            seller,
             credit_id,
-            seller: seller.clone(),
+            payment_token,
             price_per_ton,
             amount_tons,
-            status: ListingStatus::Active,
-            created_at: env.ledger().timestamp(),
+            Pricing::Fixed,
+            None,
+            Some(allowed_buyer),
+        )
+    }
+
+    /// Create a Dutch listing whose price per ton falls from `start_price` to the terms' floor
+    /// price over their duration, linearly or in steps of `step_seconds`
+    pub fn create_dutch_listing(
+        env: Env,
+        seller: Address,
+        credit_id: u64,
+        payment_token: Address,
+        start_price: i128,
+        amount_tons: u64,
+        terms: DutchTerms,
+    ) -> Result<u64, Error> {
+        seller.require_auth();
+        
+        if terms.floor_price <= 0 || start_price <= terms.floor_price {
+            return Err(Error::InvalidPrice);
+        }
+        
+        if terms.duration == 0 || terms.step_seconds > terms.duration {
+            return Err(Error::InvalidSchedule);
+        }
+        
+        let start_time = env.ledger().timestamp();
+        let schedule = DutchSchedule {
+            floor_price: terms.floor_price,
+            start_time,
+            end_time: start_time + terms.duration,
+            step_seconds: terms.step_seconds,
         };
         
-        env.storage().persistent().set(&DataKey::Listing(listing_id), &listing);
+        Self::open_listing(&env, seller, credit_id, payment_token, start_price, amount_tons, Pricing::Dutch(schedule), None, None)
+    }
+
+    /// Purchase `tons` from a listing; the listing stays active until fully filled.
+    /// Reverts if the price in force is above `max_price_per_ton`.
+    pub fn buy_credit(env: Env, buyer: Address, listing_id: u64, tons: u64, max_price_per_ton: i128) -> Result<(), Error> {
+        buyer.require_auth();
         
-        // Add to active listings
-        let mut active_listings: Vec<u64> = env.storage().instance().get(&DataKey::ActiveListings).unwrap();
-        active_listings.push_back(listing_id);
-        env.storage().instance().set(&DataKey::ActiveListings, &active_listings);
+        let mut listing = Self::load_listing(&env, listing_id)?;
+        Self::check_price_limit(&env, &listing, max_price_per_ton)?;
+        Self::lock_listing(&env, listing_id)?;
         
-        // Update next listing ID
-        env.storage().instance().set(&DataKey::NextListingId, &(listing_id + 1));
+        // The fill commits the listing's new state before the payment is transferred
+        let mut payouts = Map::new(&env);
+        Self::fill_listing(&env, &mut listing, &buyer, tons, &mut payouts)?;
+        Self::disburse(&env, &listing.payment_token, &buyer, &payouts);
         
-        env.events().publish(
-            (Symbol::new(&env, "listing_created"),),
-            (listing_id, credit_id, seller, price_per_ton, amount_tons)
-        );
+        Self::unlock_listing(&env, listing_id);
         
-        listing_id
+        Ok(())
     }
 
-    /// Purchase a carbon credit from the marketplace
-    pub fn buy_credit(env: Env, buyer: Address, listing_id: u64) {
+    /// Fill several `(listing_id, tons)` orders atomically, paying each payee once.
+    /// All listings must be priced in the same token. Reverts if the total exceeds
+    /// `max_total_price`; returns the total paid.
+    pub fn buy_many(env: Env, buyer: Address, orders: Vec<(u64, u64)>, max_total_price: i128) -> Result<i128, Error> {
         buyer.require_auth();
         
-        let mut listing: Listing = env.storage()
-            .persistent()
-            .get(&DataKey::Listing(listing_id))
-            .unwrap();
-        
-        if listing.status != ListingStatus::Active {
-            panic!("Listing is not active");
+        if orders.is_empty() {
+            return Err(Error::InvalidAmount);
         }
         
-        let total_price = listing.price_per_ton * listing.amount_tons as i128;
+        let mut payouts = Map::new(&env);
+        let mut payment_token: Option<Address> = None;
+        let mut total_price: i128 = 0;
+        let mut locked: Vec<u64> = Vec::new(&env);
         
-        // Calculate marketplace fee
-        let marketplace_fee: u32 = env.storage().instance().get(&DataKey::MarketplaceFee).unwrap();
-        let fee_amount = (total_price * marketplace_fee as i128) / 10000;
-        let seller_amount = total_price - fee_amount;
+        for (listing_id, tons) in orders.iter() {
+            let mut listing = Self::load_listing(&env, listing_id)?;
+            
+            // A cart may fill the same listing more than once
+            if !locked.contains(listing_id) {
+                Self::lock_listing(&env, listing_id)?;
+                locked.push_back(listing_id);
+            }
+            
+            match &payment_token {
+                Some(token) if *token != listing.payment_token => return Err(Error::TokenMismatch),
+                Some(_) => {}
+                None => payment_token = Some(listing.payment_token.clone()),
+            }
+            
+            let (_, price) = Self::fill_listing(&env, &mut listing, &buyer, tons, &mut payouts)?;
+            total_price += price;
+        }
         
-        let payment_token: Address = env.storage().instance().get(&DataKey::PaymentToken).unwrap();
-        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        if total_price > max_total_price {
+            return Err(Error::PriceLimitExceeded);
+        }
         
-        // Transfer payment token from buyer to seller
-        let token_client = token::Client::new(&env, &payment_token);
-        token_client.transfer(&buyer, &listing.seller, &seller_amount);
+        let payment_token = payment_token.ok_or(Error::InvalidAmount)?;
+        Self::disburse(&env, &payment_token, &buyer, &payouts);
         
-        // Transfer fee to marketplace admin
-        if fee_amount > 0 {
-            token_client.transfer(&buyer, &admin, &fee_amount);
+        for listing_id in locked.iter() {
+            Self::unlock_listing(&env, listing_id);
         }
         
-        // Transfer carbon credit ownership
-        let carbon_credit_contract: Address = env.storage()
-            .instance()
-            .get(&DataKey::CarbonCreditContract)
-            .unwrap();
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "cart_purchased"),),
+            (buyer, orders.len(), total_price)
+        );
         
-        // Mark listing as sold
-        listing.status = ListingStatus::Sold;
-        env.storage().persistent().set(&DataKey::Listing(listing_id), &listing);
+        Ok(total_price)
+    }
+
+    /// Purchase `tons` from a listing and retire them for the buyer in the same call.
+    /// Reverts if the price in force is above `max_price_per_ton`. Returns the retirement record id.
+    #[allow(clippy::too_many_arguments)]
+    pub fn buy_and_retire(
+        env: Env,
+        buyer: Address,
+        listing_id: u64,
+        tons: u64,
+        max_price_per_ton: i128,
+        beneficiary: String,
+        reason: String,
+        reporting_period: String,
+    ) -> Result<u64, Error> {
+        buyer.require_auth();
         
-        // Remove from active listings
-        Self::remove_from_active_listings(&env, listing_id);
+        let mut listing = Self::load_listing(&env, listing_id)?;
+        Self::check_price_limit(&env, &listing, max_price_per_ton)?;
+        Self::lock_listing(&env, listing_id)?;
         
-        env.events().publish(
-            (Symbol::new(&env, "credit_purchased"),),
-            (listing_id, buyer, listing.seller.clone(), total_price)
+        let mut payouts = Map::new(&env);
+        let (credit_id, _) = Self::fill_listing(&env, &mut listing, &buyer, tons, &mut payouts)?;
+        Self::disburse(&env, &listing.payment_token, &buyer, &payouts);
+        
+        Self::unlock_listing(&env, listing_id);
+        
+        let retirement_id = Self::carbon_credit_client(&env)?.retire_credit(
+            &buyer,
+            &credit_id,
+            &beneficiary,
+            &reason,
+            &reporting_period,
         );
+        
+        Ok(retirement_id)
     }
 
     /// Cancel a listing
-    pub fn cancel_listing(env: Env, seller: Address, listing_id: u64) {
+    pub fn cancel_listing(env: Env, seller: Address, listing_id: u64) -> Result<(), Error> {
         seller.require_auth();
         
-        let mut listing: Listing = env.storage()
-            .persistent()
-            .get(&DataKey::Listing(listing_id))
-            .unwrap();
+        let mut listing = Self::load_listing(&env, listing_id)?;
         
         if listing.seller != seller {
-            panic!("Not the seller");
+            return Err(Error::NotSeller);
         }
         
         if listing.status != ListingStatus::Active {
-            panic!("Listing is not active");
+            return Err(Error::ListingNotActive);
         }
         
+        Self::lock_listing(&env, listing_id)?;
+        
         listing.status = ListingStatus::Cancelled;
         env.storage().persistent().set(&DataKey::Listing(listing_id), &listing);
         
         // Remove from active listings
-        Self::remove_from_active_listings(&env, listing_id);
+        Self::unindex_listing(&env, &listing);
+        
+        // Return the escrowed carbon credit to the seller
+        Self::carbon_credit_client(&env)?.transfer(&env.current_contract_address(), &seller, &listing.credit_id);
         
+        Self::unlock_listing(&env, listing_id);
+        
+        #[allow(deprecated)]
         env.events().publish(
             (Symbol::new(&env, "listing_cancelled"),),
             (listing_id, seller)
         );
+        
+        Ok(())
     }
 
     /// Update listing price
-    pub fn update_price(env: Env, seller: Address, listing_id: u64, new_price: i128) {
+    pub fn update_price(env: Env, seller: Address, listing_id: u64, new_price: i128) -> Result<(), Error> {
         seller.require_auth();
         
         if new_price <= 0 {
-            panic!("Price must be positive");
+            return Err(Error::InvalidPrice);
         }
         
-        let mut listing: Listing = env.storage()
-            .persistent()
-            .get(&DataKey::Listing(listing_id))
-            .unwrap();
+        let mut listing = Self::load_listing(&env, listing_id)?;
         
         if listing.seller != seller {
-            panic!("Not the seller");
+            return Err(Error::NotSeller);
         }
         
         if listing.status != ListingStatus::Active {
-            panic!("Listing is not active");
+            return Err(Error::ListingNotActive);
+        }
+        
+        if listing.pricing != Pricing::Fixed {
+            return Err(Error::NotFixedPrice);
         }
         
         listing.price_per_ton = new_price;
         env.storage().persistent().set(&DataKey::Listing(listing_id), &listing);
         
+        #[allow(deprecated)]
         env.events().publish(
             (Symbol::new(&env, "price_updated"),),
             (listing_id, new_price)
         );
+        
+        Ok(())
     }
 
-    /// Get listing details
-    pub fn get_listing(env: Env, listing_id: u64) -> Listing {
-        env.storage()
-            .persistent()
-            .get(&DataKey::Listing(listing_id))
-            .unwrap()
-    }
-
-    /// Get all active listings
-    pub fn get_active_listings(env: Env) -> Vec<u64> {
-        env.storage().instance().get(&DataKey::ActiveListings).unwrap()
-    }
-
-    /// Get marketplace fee
-    pub fn get_marketplace_fee(env: Env) -> u32 {
-        env.storage().instance().get(&DataKey::MarketplaceFee).unwrap()
-    }
-
-    /// Update marketplace fee (admin only)
-    pub fn update_marketplace_fee(env: Env, admin: Address, new_fee: u32) {
-        admin.require_auth();
+    /// Offer to buy `tons` from a listing at `price_per_ton`, escrowing the full amount.
+    /// The seller can accept or counter the offer until `expires_at`.
+    pub fn make_offer(
+        env: Env,
+        buyer: Address,
+        listing_id: u64,
+        price_per_ton: i128,
+        tons: u64,
+        expires_at: u64,
+    ) -> Result<u64, Error> {
+        buyer.require_auth();
+        
+        if price_per_ton <= 0 {
+            return Err(Error::InvalidPrice);
+        }
+        
+        if expires_at <= env.ledger().timestamp() {
+            return Err(Error::InvalidEndTime);
+        }
+        
+        let listing = Self::load_listing(&env, listing_id)?;
+        
+        if listing.status != ListingStatus::Active {
+            return Err(Error::ListingNotActive);
+        }
+        
+        if Self::is_expired(&env, &listing) {
+            return Err(Error::ListingExpired);
+        }
         
-        let stored_admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
-        if admin != stored_admin {
-            panic!("Not authorized");
+        if let Some(allowed_buyer) = &listing.allowed_buyer {
+            if *allowed_buyer != buyer {
+                return Err(Error::NotAllowedBuyer);
+            }
         }
         
-        if new_fee > 1000 {
-            panic!("Fee too high (max 10%)");
+        if tons == 0 || tons > listing.remaining_tons {
+            return Err(Error::InvalidAmount);
         }
         
-        env.storage().instance().set(&DataKey::MarketplaceFee, &new_fee);
+        Self::require_accepted_token(&env, &listing.payment_token)?;
         
+        let offer_id: u64 = env.storage()
+            .instance()
+            .get(&DataKey::NextOfferId)
+            .ok_or(Error::NotInitialized)?;
+        
+        token::Client::new(&env, &listing.payment_token)
+            .transfer(&buyer, env.current_contract_address(), &(price_per_ton * tons as i128));
+        
+        let offer = Offer {
+            offer_id,
+            listing_id,
+            buyer: buyer.clone(),
+            price_per_ton,
+            tons,
+            counter_price: None,
+            expires_at,
+            status: OfferStatus::Open,
+            created_at: env.ledger().timestamp(),
+        };
+        
+        env.storage().persistent().set(&DataKey::Offer(offer_id), &offer);
+        
+        let mut listing_offers = Self::get_listing_offers(env.clone(), listing_id);
+        listing_offers.push_back(offer_id);
+        env.storage().persistent().set(&DataKey::ListingOffers(listing_id), &listing_offers);
+        
+        env.storage().instance().set(&DataKey::NextOfferId, &(offer_id + 1));
+        
+        #[allow(deprecated)]
         env.events().publish(
-            (Symbol::new(&env, "fee_updated"),),
-            new_fee
+            (Symbol::new(&env, "offer_made"),),
+            (offer_id, listing_id, buyer, price_per_ton, tons)
         );
+        
+        Ok(offer_id)
     }
 
-    // Helper function to remove listing from active listings
-    fn remove_from_active_listings(env: &Env, listing_id: u64) {
-        let active_listings: Vec<u64> = env.storage().instance().get(&DataKey::ActiveListings).unwrap();
-        let mut new_active_listings: Vec<u64> = Vec::new(&env);
+    /// Accept an open offer, selling the offered tons at the offer price out of its escrow.
+    /// Returns the credit delivered to the buyer.
+    pub fn accept_offer(env: Env, seller: Address, offer_id: u64) -> Result<u64, Error> {
+        seller.require_auth();
         
-        for id in active_listings.iter() {
-            if id != listing_id {
-                new_active_listings.push_back(id);
-            }
+        let mut offer = Self::load_offer(&env, offer_id)?;
+        
+        if offer.status != OfferStatus::Open {
+            return Err(Error::OfferNotOpen);
         }
         
-        env.storage().instance().set(&DataKey::ActiveListings, &new_active_listings);
+        if env.ledger().timestamp() >= offer.expires_at {
+            return Err(Error::OfferExpired);
+        }
+        
+        let mut listing = Self::load_listing(&env, offer.listing_id)?;
+        
+        if listing.seller != seller {
+            return Err(Error::NotSeller);
+        }
+        
+        let price_per_ton = offer.price_per_ton;
+        Self::settle_offer(&env, &mut offer, &mut listing, price_per_ton)
+    }
+
+    /// Answer an open offer with a different price per ton for the buyer to accept
+    pub fn counter_offer(env: Env, seller: Address, offer_id: u64, price_per_ton: i128) -> Result<(), Error> {
+        seller.require_auth();
+        
+        if price_per_ton <= 0 {
+            return Err(Error::InvalidPrice);
+        }
+        
+        let mut offer = Self::load_offer(&env, offer_id)?;
+        
+        if offer.status != OfferStatus::Open {
+            return Err(Error::OfferNotOpen);
+        }
+        
+        if env.ledger().timestamp() >= offer.expires_at {
+            return Err(Error::OfferExpired);
+        }
+        
+        let listing = Self::load_listing(&env, offer.listing_id)?;
+        
+        if listing.seller != seller {
+            return Err(Error::NotSeller);
+        }
+        
+        if listing.status != ListingStatus::Active {
+            return Err(Error::ListingNotActive);
+        }
+        
+        offer.counter_price = Some(price_per_ton);
+        offer.status = OfferStatus::Countered;
+        env.storage().persistent().set(&DataKey::Offer(offer_id), &offer);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "offer_countered"),),
+            (offer_id, offer.listing_id, price_per_ton)
+        );
+        
+        Ok(())
+    }
+
+    /// Accept the seller's counter price. A counter above the original offer is topped up
+    /// from the buyer; a lower one refunds the difference. Returns the credit delivered.
+    pub fn accept_counter_offer(env: Env, buyer: Address, offer_id: u64) -> Result<u64, Error> {
+        buyer.require_auth();
+        
+        let mut offer = Self::load_offer(&env, offer_id)?;
+        
+        if offer.buyer != buyer {
+            return Err(Error::NotOfferBuyer);
+        }
+        
+        if offer.status != OfferStatus::Countered {
+            return Err(Error::NotCountered);
+        }
+        
+        if env.ledger().timestamp() >= offer.expires_at {
+            return Err(Error::OfferExpired);
+        }
+        
+        let counter_price = offer.counter_price.ok_or(Error::NotCountered)?;
+        let mut listing = Self::load_listing(&env, offer.listing_id)?;
+        
+        Self::settle_offer(&env, &mut offer, &mut listing, counter_price)
+    }
+
+    /// Withdraw an unaccepted offer and refund its escrow
+    pub fn withdraw_offer(env: Env, buyer: Address, offer_id: u64) -> Result<(), Error> {
+        buyer.require_auth();
+        
+        let mut offer = Self::load_offer(&env, offer_id)?;
+        
+        if offer.buyer != buyer {
+            return Err(Error::NotOfferBuyer);
+        }
+        
+        Self::close_offer(&env, &mut offer, OfferStatus::Withdrawn)?;
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "offer_withdrawn"),),
+            (offer_id, buyer)
+        );
+        
+        Ok(())
+    }
+
+    /// Return the escrow of an offer that lapsed unaccepted. Anyone may call this.
+    pub fn expire_offer(env: Env, offer_id: u64) -> Result<(), Error> {
+        let mut offer = Self::load_offer(&env, offer_id)?;
+        
+        if env.ledger().timestamp() < offer.expires_at {
+            return Err(Error::OfferNotExpired);
+        }
+        
+        Self::close_offer(&env, &mut offer, OfferStatus::Expired)?;
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "offer_expired"),),
+            (offer_id, offer.buyer)
+        );
+        
+        Ok(())
+    }
+
+    /// Get offer details
+    pub fn get_offer(env: Env, offer_id: u64) -> Result<Offer, Error> {
+        Self::load_offer(&env, offer_id)
+    }
+
+    /// Get the ids of all offers made on a listing
+    pub fn get_listing_offers(env: Env, listing_id: u64) -> Vec<u64> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::ListingOffers(listing_id))
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Post a standing bid, escrowing `max_price_per_ton * amount_tons` of the payment token
+    pub fn create_bid(
+        env: Env,
+        buyer: Address,
+        payment_token: Address,
+        max_price_per_ton: i128,
+        amount_tons: u64,
+        filter: BidFilter,
+    ) -> Result<u64, Error> {
+        buyer.require_auth();
+        
+        if max_price_per_ton <= 0 {
+            return Err(Error::InvalidPrice);
+        }
+        
+        if amount_tons == 0 {
+            return Err(Error::InvalidAmount);
+        }
+        
+        Self::require_accepted_token(&env, &payment_token)?;
+        
+        let bid_id: u64 = env.storage()
+            .instance()
+            .get(&DataKey::NextBidId)
+            .ok_or(Error::NotInitialized)?;
+        
+        // Escrow the maximum the bid can cost
+        let escrow_amount = max_price_per_ton * amount_tons as i128;
+        token::Client::new(&env, &payment_token)
+            .transfer(&buyer, env.current_contract_address(), &escrow_amount);
+        
+        let bid = Bid {
+            bid_id,
+            buyer: buyer.clone(),
+            payment_token,
+            max_price_per_ton,
+            amount_tons,
+            remaining_tons: amount_tons,
+            filter,
+            status: BidStatus::Active,
+            created_at: env.ledger().timestamp(),
+        };
+        
+        env.storage().persistent().set(&DataKey::Bid(bid_id), &bid);
+        
+        // Add to active bids
+        let mut active_bids: Vec<u64> = env.storage()
+            .persistent()
+            .get(&DataKey::ActiveBids)
+            .ok_or(Error::NotInitialized)?;
+        active_bids.push_back(bid_id);
+        env.storage().persistent().set(&DataKey::ActiveBids, &active_bids);
+        
+        env.storage().instance().set(&DataKey::NextBidId, &(bid_id + 1));
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "bid_created"),),
+            (bid_id, buyer, max_price_per_ton, amount_tons)
+        );
+        
+        Ok(bid_id)
+    }
+
+    /// Cancel a bid and refund the unfilled escrow
+    pub fn cancel_bid(env: Env, buyer: Address, bid_id: u64) -> Result<(), Error> {
+        buyer.require_auth();
+        
+        let mut bid = Self::load_bid(&env, bid_id)?;
+        
+        if bid.buyer != buyer {
+            return Err(Error::NotBidder);
+        }
+        
+        if bid.status != BidStatus::Active {
+            return Err(Error::BidNotActive);
+        }
+        
+        bid.status = BidStatus::Cancelled;
+        env.storage().persistent().set(&DataKey::Bid(bid_id), &bid);
+        Self::remove_from_active_bids(&env, bid_id)?;
+        
+        let refund = bid.max_price_per_ton * bid.remaining_tons as i128;
+        token::Client::new(&env, &bid.payment_token)
+            .transfer(&env.current_contract_address(), &buyer, &refund);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "bid_cancelled"),),
+            (bid_id, buyer)
+        );
+        
+        Ok(())
+    }
+
+    /// Sell `tons` of a credit directly into a bid at the bid's price
+    pub fn fill_bid(env: Env, seller: Address, bid_id: u64, credit_id: u64, tons: u64) -> Result<u64, Error> {
+        seller.require_auth();
+        
+        let mut bid = Self::load_bid(&env, bid_id)?;
+        
+        if bid.status != BidStatus::Active {
+            return Err(Error::BidNotActive);
+        }
+        
+        if tons == 0 || tons > bid.remaining_tons {
+            return Err(Error::InvalidAmount);
+        }
+        
+        let carbon_credit_client = Self::carbon_credit_client(&env)?;
+        let metadata = Self::check_credit(&carbon_credit_client, credit_id, &seller, tons)?;
+        
+        if !Self::bid_accepts(&bid, &metadata) {
+            return Err(Error::CreditNotAccepted);
+        }
+        
+        bid.remaining_tons -= tons;
+        if bid.remaining_tons == 0 {
+            bid.status = BidStatus::Filled;
+            Self::remove_from_active_bids(&env, bid_id)?;
+        }
+        env.storage().persistent().set(&DataKey::Bid(bid_id), &bid);
+        
+        // Pay the seller out of the bid's escrow
+        let total_price = bid.max_price_per_ton * tons as i128;
+        let fee = Self::pay_seller(&env, &bid.payment_token, &env.current_contract_address(), &seller, &metadata, total_price)?;
+        
+        // Deliver the tons, splitting them off the seller's credit when needed
+        let delivered_id = if tons == metadata.amount_tons {
+            credit_id
+        } else {
+            let amounts = Vec::from_array(&env, [tons, metadata.amount_tons - tons]);
+            carbon_credit_client.split_credit(&seller, &credit_id, &amounts).get_unchecked(0)
+        };
+        carbon_credit_client.transfer(&seller, &bid.buyer, &delivered_id);
+        
+        Self::record_trade(&env, delivered_id, &metadata, &bid.buyer, &seller, &bid.payment_token, tons, total_price, fee)?;
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "bid_filled"),),
+            (bid_id, seller, tons, total_price)
+        );
+        
+        Ok(delivered_id)
+    }
+
+    /// Cross an active bid with an active listing priced at or below the bid.
+    /// Trades at the listing price; the buyer is refunded the difference. Returns tons matched.
+    pub fn match_orders(env: Env, bid_id: u64, listing_id: u64) -> Result<u64, Error> {
+        let mut bid = Self::load_bid(&env, bid_id)?;
+        let mut listing = Self::load_listing(&env, listing_id)?;
+        
+        if bid.status != BidStatus::Active {
+            return Err(Error::BidNotActive);
+        }
+        
+        if listing.status != ListingStatus::Active {
+            return Err(Error::ListingNotActive);
+        }
+        
+        if listing.payment_token != bid.payment_token {
+            return Err(Error::TokenMismatch);
+        }
+        
+        let price_per_ton = Self::current_price(&env, &listing);
+        if price_per_ton > bid.max_price_per_ton {
+            return Err(Error::PriceNotMatched);
+        }
+        
+        let metadata = Self::carbon_credit_client(&env)?.get_credit(&listing.credit_id);
+        if !Self::bid_accepts(&bid, &metadata) {
+            return Err(Error::CreditNotAccepted);
+        }
+        
+        let tons = core::cmp::min(bid.remaining_tons, listing.remaining_tons);
+        
+        bid.remaining_tons -= tons;
+        if bid.remaining_tons == 0 {
+            bid.status = BidStatus::Filled;
+            Self::remove_from_active_bids(&env, bid_id)?;
+        }
+        env.storage().persistent().set(&DataKey::Bid(bid_id), &bid);
+        
+        // Settle from the bid's escrow at the listing price
+        Self::lock_listing(&env, listing_id)?;
+        let mut payouts = Map::new(&env);
+        Self::fill_listing(&env, &mut listing, &bid.buyer, tons, &mut payouts)?;
+        Self::disburse(&env, &bid.payment_token, &env.current_contract_address(), &payouts);
+        
+        let refund = (bid.max_price_per_ton - price_per_ton) * tons as i128;
+        if refund > 0 {
+            token::Client::new(&env, &bid.payment_token)
+                .transfer(&env.current_contract_address(), &bid.buyer, &refund);
+        }
+        
+        Self::unlock_listing(&env, listing_id);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "orders_matched"),),
+            (bid_id, listing_id, tons, price_per_ton)
+        );
+        
+        Ok(tons)
+    }
+
+    /// Auction a whole credit, escrowing it until `settle_auction` after `end_time`
+    pub fn create_auction(
+        env: Env,
+        seller: Address,
+        credit_id: u64,
+        payment_token: Address,
+        reserve_price: i128,
+        min_increment: i128,
+        end_time: u64,
+    ) -> Result<u64, Error> {
+        seller.require_auth();
+        
+        if reserve_price <= 0 || min_increment <= 0 {
+            return Err(Error::InvalidPrice);
+        }
+        
+        if end_time <= env.ledger().timestamp() {
+            return Err(Error::InvalidEndTime);
+        }
+        
+        Self::require_accepted_token(&env, &payment_token)?;
+        
+        let auction_id: u64 = env.storage()
+            .instance()
+            .get(&DataKey::NextAuctionId)
+            .ok_or(Error::NotInitialized)?;
+        
+        let carbon_credit_client = Self::carbon_credit_client(&env)?;
+        let amount_tons = Self::check_credit(&carbon_credit_client, credit_id, &seller, 0)?.amount_tons;
+        
+        // Move the credit into escrow until the auction is settled
+        carbon_credit_client.transfer(&seller, &env.current_contract_address(), &credit_id);
+        
+        let auction = Auction {
+            auction_id,
+            credit_id,
+            seller: seller.clone(),
+            payment_token,
+            amount_tons,
+            reserve_price,
+            min_increment,
+            highest_bidder: None,
+            highest_bid: 0,
+            end_time,
+            status: AuctionStatus::Active,
+            created_at: env.ledger().timestamp(),
+        };
+        
+        env.storage().persistent().set(&DataKey::Auction(auction_id), &auction);
+        env.storage().instance().set(&DataKey::NextAuctionId, &(auction_id + 1));
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "auction_created"),),
+            (auction_id, credit_id, seller, reserve_price, end_time)
+        );
+        
+        Ok(auction_id)
+    }
+
+    /// Bid on an auction; the bid is escrowed and the previous highest bidder refunded
+    pub fn place_bid(env: Env, bidder: Address, auction_id: u64, amount: i128) -> Result<(), Error> {
+        bidder.require_auth();
+        
+        let mut auction = Self::load_auction(&env, auction_id)?;
+        
+        if auction.status != AuctionStatus::Active {
+            return Err(Error::AuctionNotActive);
+        }
+        
+        if env.ledger().timestamp() >= auction.end_time {
+            return Err(Error::AuctionEnded);
+        }
+        
+        let min_bid = match auction.highest_bidder {
+            Some(_) => auction.highest_bid + auction.min_increment,
+            None => auction.reserve_price,
+        };
+        if amount < min_bid {
+            return Err(Error::BidTooLow);
+        }
+        
+        // Commit the new highest bid before any transfer
+        let previous_bidder = auction.highest_bidder.replace(bidder.clone());
+        let previous_bid = auction.highest_bid;
+        auction.highest_bid = amount;
+        env.storage().persistent().set(&DataKey::Auction(auction_id), &auction);
+        
+        let token_client = token::Client::new(&env, &auction.payment_token);
+        token_client.transfer(&bidder, env.current_contract_address(), &amount);
+        
+        // Refund the bidder being outbid
+        if let Some(previous_bidder) = previous_bidder {
+            token_client.transfer(&env.current_contract_address(), &previous_bidder, &previous_bid);
+        }
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "auction_bid"),),
+            (auction_id, bidder, amount)
+        );
+        
+        Ok(())
+    }
+
+    /// Settle an auction once it has ended. Anyone may call this.
+    pub fn settle_auction(env: Env, auction_id: u64) -> Result<(), Error> {
+        let mut auction = Self::load_auction(&env, auction_id)?;
+        
+        if auction.status != AuctionStatus::Active {
+            return Err(Error::AuctionNotActive);
+        }
+        
+        if env.ledger().timestamp() < auction.end_time {
+            return Err(Error::AuctionNotEnded);
+        }
+        
+        // Commit the outcome before any transfer
+        auction.status = match auction.highest_bidder {
+            Some(_) => AuctionStatus::Sold,
+            None => AuctionStatus::Unsold,
+        };
+        env.storage().persistent().set(&DataKey::Auction(auction_id), &auction);
+        
+        Self::close_auction(
+            &env,
+            auction.credit_id,
+            &auction.seller,
+            &auction.payment_token,
+            auction.amount_tons,
+            &auction.highest_bidder,
+            auction.highest_bid,
+        )?;
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "auction_settled"),),
+            (auction_id, auction.highest_bidder, auction.highest_bid)
+        );
+        
+        Ok(())
+    }
+
+    /// Get auction details
+    pub fn get_auction(env: Env, auction_id: u64) -> Result<Auction, Error> {
+        Self::load_auction(&env, auction_id)
+    }
+
+    /// Open a sealed-bid auction for a whole credit, escrowing it until `finalize`
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_sealed_auction(
+        env: Env,
+        seller: Address,
+        credit_id: u64,
+        payment_token: Address,
+        reserve_price: i128,
+        deposit: i128,
+        commit_end: u64,
+        reveal_end: u64,
+    ) -> Result<u64, Error> {
+        seller.require_auth();
+        
+        if reserve_price <= 0 || deposit <= 0 {
+            return Err(Error::InvalidPrice);
+        }
+        
+        if commit_end <= env.ledger().timestamp() || reveal_end <= commit_end {
+            return Err(Error::InvalidEndTime);
+        }
+        
+        Self::require_accepted_token(&env, &payment_token)?;
+        
+        let auction_id: u64 = env.storage()
+            .instance()
+            .get(&DataKey::NextAuctionId)
+            .ok_or(Error::NotInitialized)?;
+        
+        let carbon_credit_client = Self::carbon_credit_client(&env)?;
+        let amount_tons = Self::check_credit(&carbon_credit_client, credit_id, &seller, 0)?.amount_tons;
+        
+        // Move the credit into escrow until the auction is finalized
+        carbon_credit_client.transfer(&seller, &env.current_contract_address(), &credit_id);
+        
+        let auction = SealedAuction {
+            auction_id,
+            credit_id,
+            seller: seller.clone(),
+            payment_token,
+            amount_tons,
+            reserve_price,
+            deposit,
+            commit_end,
+            reveal_end,
+            highest_bidder: None,
+            highest_bid: 0,
+            status: AuctionStatus::Active,
+            created_at: env.ledger().timestamp(),
+        };
+        
+        env.storage().persistent().set(&DataKey::SealedAuction(auction_id), &auction);
+        env.storage().persistent().set(&DataKey::SealedBidders(auction_id), &Vec::<Address>::new(&env));
+        env.storage().instance().set(&DataKey::NextAuctionId, &(auction_id + 1));
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "sealed_auction_created"),),
+            (auction_id, credit_id, seller, commit_end, reveal_end)
+        );
+        
+        Ok(auction_id)
+    }
+
+    /// Commit a sealed bid, escrowing the auction's deposit
+    pub fn commit_bid(env: Env, bidder: Address, auction_id: u64, commitment: BytesN<32>) -> Result<(), Error> {
+        bidder.require_auth();
+        
+        let auction = Self::load_sealed_auction(&env, auction_id)?;
+        
+        if auction.status != AuctionStatus::Active {
+            return Err(Error::AuctionNotActive);
+        }
+        
+        if env.ledger().timestamp() >= auction.commit_end {
+            return Err(Error::NotCommitPhase);
+        }
+        
+        let bid_key = DataKey::SealedBid(auction_id, bidder.clone());
+        if env.storage().persistent().has(&bid_key) {
+            return Err(Error::AlreadyCommitted);
+        }
+        
+        token::Client::new(&env, &auction.payment_token)
+            .transfer(&bidder, env.current_contract_address(), &auction.deposit);
+        
+        let sealed_bid = SealedBid {
+            commitment,
+            revealed: false,
+            amount: 0,
+        };
+        env.storage().persistent().set(&bid_key, &sealed_bid);
+        
+        let mut bidders = Self::get_sealed_bidders(&env, auction_id);
+        bidders.push_back(bidder.clone());
+        env.storage().persistent().set(&DataKey::SealedBidders(auction_id), &bidders);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "bid_committed"),),
+            (auction_id, bidder)
+        );
+        
+        Ok(())
+    }
+
+    /// Reveal a committed bid. A new highest bid at or above the reserve is escrowed
+    /// and the bidder it displaces is refunded.
+    pub fn reveal_bid(env: Env, bidder: Address, auction_id: u64, amount: i128, salt: BytesN<32>) -> Result<(), Error> {
+        bidder.require_auth();
+        
+        let mut auction = Self::load_sealed_auction(&env, auction_id)?;
+        
+        if auction.status != AuctionStatus::Active {
+            return Err(Error::AuctionNotActive);
+        }
+        
+        let now = env.ledger().timestamp();
+        if now < auction.commit_end || now >= auction.reveal_end {
+            return Err(Error::NotRevealPhase);
+        }
+        
+        let bid_key = DataKey::SealedBid(auction_id, bidder.clone());
+        let mut sealed_bid: SealedBid = env.storage()
+            .persistent()
+            .get(&bid_key)
+            .ok_or(Error::NoCommitment)?;
+        
+        if sealed_bid.revealed {
+            return Err(Error::AlreadyRevealed);
+        }
+        
+        if Self::sealed_commitment(&env, auction_id, &bidder, amount, &salt) != sealed_bid.commitment {
+            return Err(Error::CommitmentMismatch);
+        }
+        
+        sealed_bid.revealed = true;
+        sealed_bid.amount = amount;
+        env.storage().persistent().set(&bid_key, &sealed_bid);
+        
+        // Only a new highest bid at or above the reserve is escrowed
+        if amount >= auction.reserve_price && amount > auction.highest_bid {
+            let previous_bidder = auction.highest_bidder.replace(bidder.clone());
+            let previous_bid = auction.highest_bid;
+            auction.highest_bid = amount;
+            env.storage().persistent().set(&DataKey::SealedAuction(auction_id), &auction);
+            
+            let token_client = token::Client::new(&env, &auction.payment_token);
+            token_client.transfer(&bidder, env.current_contract_address(), &amount);
+            
+            if let Some(previous_bidder) = previous_bidder {
+                token_client.transfer(&env.current_contract_address(), &previous_bidder, &previous_bid);
+            }
+        }
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "bid_revealed"),),
+            (auction_id, bidder, amount)
+        );
+        
+        Ok(())
+    }
+
+    /// Finalize a sealed-bid auction after the reveal deadline. Deposits are returned to
+    /// bidders who revealed; those who did not forfeit theirs to the accrued fees. Anyone may call this.
+    pub fn finalize(env: Env, auction_id: u64) -> Result<(), Error> {
+        let mut auction = Self::load_sealed_auction(&env, auction_id)?;
+        
+        if auction.status != AuctionStatus::Active {
+            return Err(Error::AuctionNotActive);
+        }
+        
+        if env.ledger().timestamp() < auction.reveal_end {
+            return Err(Error::AuctionNotEnded);
+        }
+        
+        // Sort deposits into refunds for revealed bids and forfeits for the rest
+        let mut refunds: Vec<Address> = Vec::new(&env);
+        let mut forfeited: i128 = 0;
+        for bidder in Self::get_sealed_bidders(&env, auction_id).iter() {
+            let sealed_bid: SealedBid = env.storage()
+                .persistent()
+                .get(&DataKey::SealedBid(auction_id, bidder.clone()))
+                .ok_or(Error::NoCommitment)?;
+            
+            if sealed_bid.revealed {
+                refunds.push_back(bidder);
+            } else {
+                forfeited += auction.deposit;
+            }
+        }
+        
+        // Commit the outcome and forfeits before any transfer
+        auction.status = match auction.highest_bidder {
+            Some(_) => AuctionStatus::Sold,
+            None => AuctionStatus::Unsold,
+        };
+        env.storage().persistent().set(&DataKey::SealedAuction(auction_id), &auction);
+        Self::accrue_fees(&env, &auction.payment_token, forfeited);
+        
+        Self::close_auction(
+            &env,
+            auction.credit_id,
+            &auction.seller,
+            &auction.payment_token,
+            auction.amount_tons,
+            &auction.highest_bidder,
+            auction.highest_bid,
+        )?;
+        
+        let token_client = token::Client::new(&env, &auction.payment_token);
+        for bidder in refunds.iter() {
+            token_client.transfer(&env.current_contract_address(), &bidder, &auction.deposit);
+        }
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "sealed_auction_finalized"),),
+            (auction_id, auction.highest_bidder, auction.highest_bid, forfeited)
+        );
+        
+        Ok(())
+    }
+
+    /// Get sealed-bid auction details
+    pub fn get_sealed_auction(env: Env, auction_id: u64) -> Result<SealedAuction, Error> {
+        Self::load_sealed_auction(&env, auction_id)
+    }
+
+    /// Get a bidder's sealed bid
+    pub fn get_sealed_bid(env: Env, auction_id: u64, bidder: Address) -> Result<SealedBid, Error> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::SealedBid(auction_id, bidder))
+            .ok_or(Error::NoCommitment)
+    }
+
+    /// Get bid details
+    pub fn get_bid(env: Env, bid_id: u64) -> Result<Bid, Error> {
+        Self::load_bid(&env, bid_id)
+    }
+
+    /// Get all active bids
+    pub fn get_active_bids(env: Env) -> Result<Vec<u64>, Error> {
+        env.storage().persistent().get(&DataKey::ActiveBids).ok_or(Error::NotInitialized)
+    }
+
+    /// Get listing details
+    pub fn get_listing(env: Env, listing_id: u64) -> Result<Listing, Error> {
+        Self::load_listing(&env, listing_id)
+    }
+
+    /// Get the fills recorded against a listing
+    pub fn get_listing_fills(env: Env, listing_id: u64) -> Vec<Fill> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::ListingFills(listing_id))
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Get a recorded trade
+    pub fn get_trade(env: Env, trade_id: u64) -> Result<Trade, Error> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Trade(trade_id))
+            .ok_or(Error::TradeNotFound)
+    }
+
+    /// Get a page of the trade log, oldest first, skipping the first `start` trades
+    pub fn get_trades(env: Env, start: u32, limit: u32) -> Result<Vec<Trade>, Error> {
+        let next_trade_id: u64 = env.storage()
+            .instance()
+            .get(&DataKey::NextTradeId)
+            .ok_or(Error::NotInitialized)?;
+        
+        let mut trades = Vec::new(&env);
+        let mut trade_id = start as u64 + 1;
+        while trade_id < next_trade_id && trades.len() < limit.min(MAX_PAGE_SIZE) {
+            trades.push_back(Self::get_trade(env.clone(), trade_id)?);
+            trade_id += 1;
+        }
+        
+        Ok(trades)
+    }
+
+    /// Get the last price, total volume and trade count of a project or vintage in a payment token
+    pub fn get_market_stats(env: Env, scope: StatsScope, payment_token: Address) -> MarketStats {
+        env.storage()
+            .persistent()
+            .get(&DataKey::MarketStats(scope, payment_token))
+            .unwrap_or_default()
+    }
+
+    /// Get the volume-weighted average price per ton of a project or vintage over the last
+    /// `window_days` days, including today. Returns `None` if nothing traded in the window.
+    pub fn get_vwap(env: Env, scope: StatsScope, payment_token: Address, window_days: u32) -> Result<Option<i128>, Error> {
+        if window_days == 0 || window_days > MAX_VWAP_WINDOW_DAYS {
+            return Err(Error::InvalidWindow);
+        }
+        
+        let today = env.ledger().timestamp() / SECONDS_PER_DAY;
+        let mut tons: u64 = 0;
+        let mut value: i128 = 0;
+        
+        for offset in 0..(window_days as u64).min(today + 1) {
+            let day_key = DataKey::DailyVolume(scope.clone(), payment_token.clone(), today - offset);
+            if let Some(day) = env.storage().persistent().get::<DataKey, DailyVolume>(&day_key) {
+                tons += day.tons;
+                value += day.value;
+            }
+        }
+        
+        if tons == 0 {
+            return Ok(None);
+        }
+        
+        Ok(Some(value / tons as i128))
+    }
+
+    /// Examine up to `limit` active listings from position `start`, marking expired ones as
+    /// `Expired` and returning their escrowed credit to the sellers. Anyone may call this.
+    pub fn prune_expired(env: Env, start: u32, limit: u32) -> Result<PruneResult, Error> {
+        let active_listings = Self::get_active_listings(env.clone())?;
+        let carbon_credit_client = Self::carbon_credit_client(&env)?;
+        let end = active_listings.len().min(start.saturating_add(limit.min(MAX_SCAN)));
+        let mut pruned: u32 = 0;
+        
+        for position in start..end {
+            let listing_id = active_listings.get_unchecked(position);
+            let mut listing = Self::load_listing(&env, listing_id)?;
+            if !Self::is_expired(&env, &listing) {
+                continue;
+            }
+            
+            listing.status = ListingStatus::Expired;
+            env.storage().persistent().set(&DataKey::Listing(listing_id), &listing);
+            Self::unindex_listing(&env, &listing);
+            
+            // Return the unsold remainder from escrow
+            carbon_credit_client.transfer(&env.current_contract_address(), &listing.seller, &listing.credit_id);
+            pruned += 1;
+            
+            #[allow(deprecated)]
+            env.events().publish(
+                (Symbol::new(&env, "listing_expired"),),
+                (listing_id, listing.seller)
+            );
+        }
+        
+        // Pruned listings left the active index, shifting the unscanned ones down
+        let next_start = if end < active_listings.len() {
+            Some(end - pruned)
+        } else {
+            None
+        };
+        
+        Ok(PruneResult { pruned, next_start })
+    }
+
+    /// Get the price per ton a listing would trade at right now
+    pub fn get_current_price(env: Env, listing_id: u64) -> Result<i128, Error> {
+        let listing = Self::load_listing(&env, listing_id)?;
+        Ok(Self::current_price(&env, &listing))
+    }
+
+    /// Get all active listings
+    pub fn get_active_listings(env: Env) -> Result<Vec<u64>, Error> {
+        env.storage().persistent().get(&DataKey::ActiveListings).ok_or(Error::NotInitialized)
+    }
+
+    /// Get a page of active listings from position `start`
+    pub fn get_listings(env: Env, start: u32, limit: u32) -> Result<ListingPage, Error> {
+        let filter = ListingFilter {
+            seller: None,
+            project_id: None,
+            min_vintage: None,
+            max_vintage: None,
+            min_price: None,
+            max_price: None,
+        };
+        
+        Self::query_listings(env, filter, start, limit)
+    }
+
+    /// Get a page of active listings matching `filter`, scanning candidates from position `start`.
+    /// Seller, project and bounded vintage filters are served from secondary indexes. At most
+    /// `MAX_SCAN` candidates are examined per call, so a page may come back short with a cursor.
+    pub fn query_listings(env: Env, filter: ListingFilter, start: u32, limit: u32) -> Result<ListingPage, Error> {
+        let limit = limit.min(MAX_PAGE_SIZE);
+        let candidates = Self::candidate_listings(&env, &filter)?;
+        let end = candidates.len().min(start.saturating_add(MAX_SCAN));
+        let mut listings: Vec<Listing> = Vec::new(&env);
+        let mut position = start;
+        
+        while position < end && listings.len() < limit {
+            let listing = Self::load_listing(&env, candidates.get_unchecked(position))?;
+            position += 1;
+            
+            if Self::listing_matches(&env, &listing, &filter) {
+                listings.push_back(listing);
+            }
+        }
+        
+        let next_start = if position < candidates.len() {
+            Some(position)
+        } else {
+            None
+        };
+        
+        Ok(ListingPage { listings, next_start })
+    }
+
+    /// Get marketplace fee
+    pub fn get_marketplace_fee(env: Env, payment_token: Address) -> Result<u32, Error> {
+        Self::token_fee(&env, &payment_token)
+    }
+
+    /// Update marketplace fee (admin only)
+    pub fn update_marketplace_fee(env: Env, admin: Address, payment_token: Address, new_fee: u32) -> Result<(), Error> {
+        Self::require_admin(&env, &admin)?;
+        
+        if new_fee > MAX_MARKETPLACE_FEE {
+            return Err(Error::FeeTooHigh);
+        }
+        
+        Self::require_accepted_token(&env, &payment_token)?;
+        
+        env.storage().instance().set(&DataKey::TokenFee(payment_token.clone()), &new_fee);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "fee_updated"),),
+            (payment_token, new_fee)
+        );
+        
+        Ok(())
+    }
+
+    /// Accept a new payment token with its own fee (admin only)
+    pub fn add_payment_token(env: Env, admin: Address, payment_token: Address, fee: u32) -> Result<(), Error> {
+        Self::require_admin(&env, &admin)?;
+        
+        if fee > MAX_MARKETPLACE_FEE {
+            return Err(Error::FeeTooHigh);
+        }
+        
+        let mut accepted_tokens = Self::get_payment_tokens(env.clone())?;
+        if accepted_tokens.contains(&payment_token) {
+            return Err(Error::TokenAlreadyAccepted);
+        }
+        accepted_tokens.push_back(payment_token.clone());
+        
+        env.storage().instance().set(&DataKey::AcceptedTokens, &accepted_tokens);
+        env.storage().instance().set(&DataKey::TokenFee(payment_token.clone()), &fee);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "token_added"),),
+            (payment_token, fee)
+        );
+        
+        Ok(())
+    }
+
+    /// Stop accepting a payment token for new orders (admin only).
+    /// Open orders in the token still settle at its last fee.
+    pub fn remove_payment_token(env: Env, admin: Address, payment_token: Address) -> Result<(), Error> {
+        Self::require_admin(&env, &admin)?;
+        
+        let accepted_tokens = Self::get_payment_tokens(env.clone())?;
+        let index = accepted_tokens
+            .first_index_of(&payment_token)
+            .ok_or(Error::TokenNotAccepted)?;
+        
+        let mut remaining_tokens = accepted_tokens;
+        remaining_tokens.remove(index);
+        env.storage().instance().set(&DataKey::AcceptedTokens, &remaining_tokens);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "token_removed"),),
+            payment_token
+        );
+        
+        Ok(())
+    }
+
+    /// Get the fees accrued in a payment token and not yet withdrawn
+    pub fn get_accrued_fees(env: Env, payment_token: Address) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::AccruedFees(payment_token))
+            .unwrap_or(0)
+    }
+
+    /// Withdraw accrued fees in a payment token to the treasury (admin only)
+    pub fn withdraw_fees(env: Env, admin: Address, payment_token: Address, amount: i128) -> Result<(), Error> {
+        Self::require_admin(&env, &admin)?;
+        
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+        
+        let accrued = Self::get_accrued_fees(env.clone(), payment_token.clone());
+        if amount > accrued {
+            return Err(Error::InsufficientFees);
+        }
+        
+        let treasury = Self::get_treasury(env.clone())?;
+        env.storage().instance().set(&DataKey::AccruedFees(payment_token.clone()), &(accrued - amount));
+        token::Client::new(&env, &payment_token).transfer(&env.current_contract_address(), &treasury, &amount);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "fees_withdrawn"),),
+            (payment_token, treasury, amount)
+        );
+        
+        Ok(())
+    }
+
+    /// Set the treasury address (admin only)
+    pub fn set_treasury(env: Env, admin: Address, treasury: Address) -> Result<(), Error> {
+        Self::require_admin(&env, &admin)?;
+        
+        env.storage().instance().set(&DataKey::Treasury, &treasury);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(&env, "treasury_updated"),),
+            treasury
+        );
+        
+        Ok(())
+    }
+
+    /// Get the treasury address
+    pub fn get_treasury(env: Env) -> Result<Address, Error> {
+        env.storage().instance().get(&DataKey::Treasury).ok_or(Error::NotInitialized)
+    }
+
+    /// Get the payment tokens accepted for new orders
+    pub fn get_payment_tokens(env: Env) -> Result<Vec<Address>, Error> {
+        env.storage().instance().get(&DataKey::AcceptedTokens).ok_or(Error::NotInitialized)
+    }
+
+    // Helper function to load a listing
+    fn load_listing(env: &Env, listing_id: u64) -> Result<Listing, Error> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Listing(listing_id))
+            .ok_or(Error::ListingNotFound)
+    }
+
+    // Helper function to escrow a credit and record a new active listing
+    #[allow(clippy::too_many_arguments)]
+    fn open_listing(
+        env: &Env,
+        seller: Address,
+        credit_id: u64,
+        payment_token: Address,
+        price_per_ton: i128,
+        amount_tons: u64,
+        pricing: Pricing,
+        expires_at: Option<u64>,
+        allowed_buyer: Option<Address>,
+    ) -> Result<u64, Error> {
+        if amount_tons == 0 {
+            return Err(Error::InvalidAmount);
+        }
+        
+        Self::require_accepted_token(env, &payment_token)?;
+        
+        let listing_id: u64 = env.storage()
+            .instance()
+            .get(&DataKey::NextListingId)
+            .ok_or(Error::NotInitialized)?;
+        
+        if env.storage().persistent().has(&DataKey::CreditListing(credit_id)) {
+            return Err(Error::CreditAlreadyListed);
+        }
+        
+        let carbon_credit_client = Self::carbon_credit_client(env)?;
+        let metadata = Self::check_credit(&carbon_credit_client, credit_id, &seller, amount_tons)?;
+        
+        // List only the offered tons, the seller keeps the rest of the credit
+        let credit_id = if amount_tons < metadata.amount_tons {
+            let amounts = Vec::from_array(env, [amount_tons, metadata.amount_tons - amount_tons]);
+            carbon_credit_client.split_credit(&seller, &credit_id, &amounts).get_unchecked(0)
+        } else {
+            credit_id
+        };
+        
+        // Move the credit into escrow until the listing is sold or cancelled
+        carbon_credit_client.transfer(&seller, &env.current_contract_address(), &credit_id);
+        
+        let listing = Listing {
+            listing_id,
+            credit_id,
+            project_id: metadata.project_id,
+            vintage_year: metadata.vintage_year,
+            seller: seller.clone(),
+            payment_token,
+            price_per_ton,
+            amount_tons,
+            remaining_tons: amount_tons,
+            status: ListingStatus::Active,
+            pricing,
+            expires_at,
+            allowed_buyer,
+            created_at: env.ledger().timestamp(),
+        };
+        
+        env.storage().persistent().set(&DataKey::Listing(listing_id), &listing);
+        env.storage().persistent().set(&DataKey::CreditListing(credit_id), &listing_id);
+        
+        // Add public listings to active listings and the secondary indexes
+        if listing.allowed_buyer.is_none() {
+            Self::index_listing(env, &listing);
+        }
+        
+        // Update next listing ID
+        env.storage().instance().set(&DataKey::NextListingId, &(listing_id + 1));
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(env, "listing_created"),),
+            (listing_id, credit_id, seller, price_per_ton, amount_tons)
+        );
+        
+        Ok(listing_id)
+    }
+
+    // Helper function to load an offer
+    fn load_offer(env: &Env, offer_id: u64) -> Result<Offer, Error> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Offer(offer_id))
+            .ok_or(Error::OfferNotFound)
+    }
+
+    // Helper function to fill an offer from its listing at `price_per_ton`, paying out of the
+    // offer's escrow. The buyer tops up a final price above the escrow and is refunded any excess.
+    fn settle_offer(env: &Env, offer: &mut Offer, listing: &mut Listing, price_per_ton: i128) -> Result<u64, Error> {
+        Self::lock_listing(env, listing.listing_id)?;
+        
+        let escrowed = offer.price_per_ton * offer.tons as i128;
+        
+        offer.status = OfferStatus::Accepted;
+        env.storage().persistent().set(&DataKey::Offer(offer.offer_id), offer);
+        
+        let mut payouts = Map::new(env);
+        let (credit_id, total_price) = Self::fill_listing_at(env, listing, &offer.buyer, offer.tons, price_per_ton, &mut payouts)?;
+        
+        let token_client = token::Client::new(env, &listing.payment_token);
+        if total_price > escrowed {
+            token_client.transfer(&offer.buyer, env.current_contract_address(), &(total_price - escrowed));
+        }
+        
+        Self::disburse(env, &listing.payment_token, &env.current_contract_address(), &payouts);
+        
+        if escrowed > total_price {
+            token_client.transfer(&env.current_contract_address(), &offer.buyer, &(escrowed - total_price));
+        }
+        
+        Self::unlock_listing(env, listing.listing_id);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(env, "offer_accepted"),),
+            (offer.offer_id, listing.listing_id, offer.buyer.clone(), price_per_ton)
+        );
+        
+        Ok(credit_id)
+    }
+
+    // Helper function to close an unaccepted offer and refund its escrow to the buyer
+    fn close_offer(env: &Env, offer: &mut Offer, status: OfferStatus) -> Result<(), Error> {
+        if offer.status != OfferStatus::Open && offer.status != OfferStatus::Countered {
+            return Err(Error::OfferNotOpen);
+        }
+        
+        offer.status = status;
+        env.storage().persistent().set(&DataKey::Offer(offer.offer_id), offer);
+        
+        let listing = Self::load_listing(env, offer.listing_id)?;
+        token::Client::new(env, &listing.payment_token)
+            .transfer(&env.current_contract_address(), &offer.buyer, &(offer.price_per_ton * offer.tons as i128));
+        
+        Ok(())
+    }
+
+    // Helper function to load a bid
+    fn load_bid(env: &Env, bid_id: u64) -> Result<Bid, Error> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Bid(bid_id))
+            .ok_or(Error::BidNotFound)
+    }
+
+    // Helper function to load an auction
+    fn load_auction(env: &Env, auction_id: u64) -> Result<Auction, Error> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Auction(auction_id))
+            .ok_or(Error::AuctionNotFound)
+    }
+
+    // Helper function to load a sealed-bid auction
+    fn load_sealed_auction(env: &Env, auction_id: u64) -> Result<SealedAuction, Error> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::SealedAuction(auction_id))
+            .ok_or(Error::AuctionNotFound)
+    }
+
+    // Helper function to hash a sealed bid the way bidders commit to it
+    fn sealed_commitment(env: &Env, auction_id: u64, bidder: &Address, amount: i128, salt: &BytesN<32>) -> BytesN<32> {
+        let mut payload = Bytes::from_array(env, &auction_id.to_be_bytes());
+        payload.append(&bidder.clone().to_xdr(env));
+        payload.extend_from_array(&amount.to_be_bytes());
+        payload.extend_from_array(&salt.to_array());
+        env.crypto().sha256(&payload).to_bytes()
+    }
+
+    // Helper function to list the bidders who committed to a sealed-bid auction
+    fn get_sealed_bidders(env: &Env, auction_id: u64) -> Vec<Address> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::SealedBidders(auction_id))
+            .unwrap_or(Vec::new(env))
+    }
+
+    // Helper function to check that a credit is live, held by `owner`, verified and covers `amount_tons`
+    fn check_credit(
+        carbon_credit_client: &CarbonCreditClient,
+        credit_id: u64,
+        owner: &Address,
+        amount_tons: u64,
+    ) -> Result<CreditMetadata, Error> {
+        let current_owner = carbon_credit_client.get_owner(&credit_id).ok_or(Error::CreditNotFound)?;
+        if current_owner != *owner {
+            return Err(Error::NotCreditOwner);
+        }
+        
+        if !carbon_credit_client.is_verified(&credit_id) {
+            return Err(Error::NotVerified);
+        }
+        
+        let metadata = carbon_credit_client.get_credit(&credit_id);
+        if amount_tons > metadata.amount_tons {
+            return Err(Error::InvalidAmount);
+        }
+        
+        Ok(metadata)
+    }
+
+    // Helper function to check a credit against a bid's filter
+    fn bid_accepts(bid: &Bid, metadata: &CreditMetadata) -> bool {
+        if metadata.verification_status != VerificationStatus::Verified {
+            return false;
+        }
+        
+        if let Some(project_id) = &bid.filter.project_id {
+            if *project_id != metadata.project_id {
+                return false;
+            }
+        }
+        
+        if let Some(min_vintage) = bid.filter.min_vintage {
+            if metadata.vintage_year < min_vintage {
+                return false;
+            }
+        }
+        
+        if let Some(max_vintage) = bid.filter.max_vintage {
+            if metadata.vintage_year > max_vintage {
+                return false;
+            }
+        }
+        
+        true
+    }
+
+    // Helper function to sell `tons` from a listing to `buyer` at the current price, adding the
+    // proceeds to `payouts` for the caller to disburse. Returns the delivered credit and the total price.
+    fn fill_listing(
+        env: &Env,
+        listing: &mut Listing,
+        buyer: &Address,
+        tons: u64,
+        payouts: &mut Map<Address, i128>,
+    ) -> Result<(u64, i128), Error> {
+        let price_per_ton = Self::current_price(env, listing);
+        Self::fill_listing_at(env, listing, buyer, tons, price_per_ton, payouts)
+    }
+
+    // Helper function to sell `tons` from a listing at a negotiated `price_per_ton`
+    fn fill_listing_at(
+        env: &Env,
+        listing: &mut Listing,
+        buyer: &Address,
+        tons: u64,
+        price_per_ton: i128,
+        payouts: &mut Map<Address, i128>,
+    ) -> Result<(u64, i128), Error> {
+        if listing.status != ListingStatus::Active {
+            return Err(Error::ListingNotActive);
+        }
+        
+        if Self::is_expired(env, listing) {
+            return Err(Error::ListingExpired);
+        }
+        
+        if let Some(allowed_buyer) = &listing.allowed_buyer {
+            if allowed_buyer != buyer {
+                return Err(Error::NotAllowedBuyer);
+            }
+        }
+        
+        if tons == 0 || tons > listing.remaining_tons {
+            return Err(Error::InvalidAmount);
+        }
+        
+        // The escrowed credit must still be live, verified and cover the unsold tons
+        let carbon_credit_client = Self::carbon_credit_client(env)?;
+        let metadata = Self::check_credit(
+            &carbon_credit_client,
+            listing.credit_id,
+            &env.current_contract_address(),
+            listing.remaining_tons,
+        )?;
+        
+        let total_price = price_per_ton * tons as i128;
+        let fee = Self::add_payouts(env, payouts, &listing.payment_token, &listing.seller, &metadata, total_price)?;
+        
+        // Commit the listing's new state before any transfer
+        listing.remaining_tons -= tons;
+        
+        // Mark listing as sold once exhausted
+        if listing.remaining_tons == 0 {
+            listing.status = ListingStatus::Sold;
+            Self::unindex_listing(env, listing);
+        }
+        env.storage().persistent().set(&DataKey::Listing(listing.listing_id), listing);
+        
+        // Carve the purchased tons out of escrow, recording the remainder's new credit id
+        let credit_id = Self::take_from_escrow(env, listing, tons)?;
+        env.storage().persistent().set(&DataKey::Listing(listing.listing_id), listing);
+        
+        let mut fills = Self::get_listing_fills(env.clone(), listing.listing_id);
+        fills.push_back(Fill {
+            buyer: buyer.clone(),
+            credit_id,
+            tons,
+            price_per_ton,
+            total_price,
+            filled_at: env.ledger().timestamp(),
+        });
+        env.storage().persistent().set(&DataKey::ListingFills(listing.listing_id), &fills);
+        Self::record_trade(env, credit_id, &metadata, buyer, &listing.seller, &listing.payment_token, tons, total_price, fee)?;
+        
+        // Deliver the purchased tons to the buyer; the caller pays out the proceeds last
+        carbon_credit_client.transfer(&env.current_contract_address(), buyer, &credit_id);
+        
+        #[allow(deprecated)]
+        env.events().publish(
+            (Symbol::new(env, "credit_purchased"),),
+            (listing.listing_id, buyer.clone(), listing.seller.clone(), tons, total_price)
+        );
+        
+        Ok((credit_id, total_price))
+    }
+
+    // Helper function to check whether a listing has passed its expiry
+    fn is_expired(env: &Env, listing: &Listing) -> bool {
+        match listing.expires_at {
+            Some(expires_at) => env.ledger().timestamp() >= expires_at,
+            None => false,
+        }
+    }
+
+    // Helper function to reject a purchase when the listing's price has moved above the buyer's limit
+    fn check_price_limit(env: &Env, listing: &Listing, max_price_per_ton: i128) -> Result<(), Error> {
+        if Self::current_price(env, listing) > max_price_per_ton {
+            return Err(Error::PriceLimitExceeded);
+        }
+        
+        Ok(())
+    }
+
+    // Helper function to compute a listing's price per ton at the current ledger time
+    fn current_price(env: &Env, listing: &Listing) -> i128 {
+        match &listing.pricing {
+            Pricing::Fixed => listing.price_per_ton,
+            Pricing::Dutch(schedule) => {
+                let duration = schedule.end_time - schedule.start_time;
+                let mut elapsed = env.ledger().timestamp().saturating_sub(schedule.start_time).min(duration);
+                if schedule.step_seconds > 0 && elapsed < duration {
+                    elapsed -= elapsed % schedule.step_seconds;
+                }
+                
+                let drop = (listing.price_per_ton - schedule.floor_price) * elapsed as i128 / duration as i128;
+                listing.price_per_ton - drop
+            }
+        }
+    }
+
+    // Helper function to pay a seller for a single sale from `payer`, returning the marketplace fee
+    fn pay_seller(
+        env: &Env,
+        payment_token: &Address,
+        payer: &Address,
+        seller: &Address,
+        credit: &CreditMetadata,
+        total_price: i128,
+    ) -> Result<i128, Error> {
+        let mut payouts = Map::new(env);
+        let fee_amount = Self::add_payouts(env, &mut payouts, payment_token, seller, credit, total_price)?;
+        Self::disburse(env, payment_token, payer, &payouts);
+        
+        Ok(fee_amount)
+    }
+
+    // Helper function to close an ended auction whose outcome is already saved: record the sale,
+    // deliver the escrowed credit to the winner (or back to the seller) and pay out the winning bid last
+    fn close_auction(
+        env: &Env,
+        credit_id: u64,
+        seller: &Address,
+        payment_token: &Address,
+        amount_tons: u64,
+        winner: &Option<Address>,
+        winning_bid: i128,
+    ) -> Result<(), Error> {
+        let carbon_credit_client = Self::carbon_credit_client(env)?;
+        
+        match winner {
+            Some(winner) => {
+                let metadata = carbon_credit_client.get_credit(&credit_id);
+                let mut payouts = Map::new(env);
+                let fee = Self::add_payouts(env, &mut payouts, payment_token, seller, &metadata, winning_bid)?;
+                Self::record_trade(env, credit_id, &metadata, winner, seller, payment_token, amount_tons, winning_bid, fee)?;
+                
+                carbon_credit_client.transfer(&env.current_contract_address(), winner, &credit_id);
+                Self::disburse(env, payment_token, &env.current_contract_address(), &payouts);
+            }
+            None => {
+                // No winning bid, return the credit to the seller
+                carbon_credit_client.transfer(&env.current_contract_address(), seller, &credit_id);
+            }
+        }
+        
+        Ok(())
+    }
+
+    // Helper function to split a sale's proceeds between the seller, the marketplace fee (keyed by
+    // the marketplace's own address) and, on resales, the credit's issuer and verifier royalties.
+    // Returns the marketplace fee.
+    fn add_payouts(
+        env: &Env,
+        payouts: &mut Map<Address, i128>,
+        payment_token: &Address,
+        seller: &Address,
+        credit: &CreditMetadata,
+        total_price: i128,
+    ) -> Result<i128, Error> {
+        let marketplace_fee = Self::token_fee(env, payment_token)?;
+        let fee_amount = (total_price * marketplace_fee as i128) / 10000;
+        Self::add_payout(payouts, &env.current_contract_address(), fee_amount);
+        
+        // Royalties on secondary sales
+        let mut royalty_amount: i128 = 0;
+        if *seller != credit.issuer {
+            let issuer_amount = (total_price * credit.royalty.issuer_bps as i128) / 10000;
+            Self::add_payout(payouts, &credit.issuer, issuer_amount);
+            royalty_amount += issuer_amount;
+            
+            if let Some(verifier) = &credit.verifier {
+                let verifier_amount = (total_price * credit.royalty.verifier_bps as i128) / 10000;
+                Self::add_payout(payouts, verifier, verifier_amount);
+                royalty_amount += verifier_amount;
+            }
+            
+            if royalty_amount > 0 {
+                #[allow(deprecated)]
+                env.events().publish(
+                    (Symbol::new(env, "royalty_paid"),),
+                    (credit.credit_id, credit.issuer.clone(), credit.verifier.clone(), royalty_amount)
+                );
+            }
+        }
+        
+        Self::add_payout(payouts, seller, total_price - fee_amount - royalty_amount);
+        
+        Ok(fee_amount)
+    }
+
+    // Helper function to append a completed sale to the trade log and fold it into the
+    // project and vintage statistics for its payment token
+    #[allow(clippy::too_many_arguments)]
+    fn record_trade(
+        env: &Env,
+        credit_id: u64,
+        credit: &CreditMetadata,
+        buyer: &Address,
+        seller: &Address,
+        payment_token: &Address,
+        tons: u64,
+        total_price: i128,
+        fee: i128,
+    ) -> Result<u64, Error> {
+        let trade_id: u64 = env.storage()
+            .instance()
+            .get(&DataKey::NextTradeId)
+            .ok_or(Error::NotInitialized)?;
+        
+        let trade = Trade {
+            trade_id,
+            credit_id,
+            project_id: credit.project_id.clone(),
+            vintage_year: credit.vintage_year,
+            buyer: buyer.clone(),
+            seller: seller.clone(),
+            payment_token: payment_token.clone(),
+            price_per_ton: total_price / tons as i128,
+            tons,
+            total_price,
+            fee,
+            traded_at: env.ledger().timestamp(),
+        };
+        
+        env.storage().persistent().set(&DataKey::Trade(trade_id), &trade);
+        env.storage().instance().set(&DataKey::NextTradeId, &(trade_id + 1));
+        
+        Self::update_stats(env, StatsScope::Project(credit.project_id.clone()), &trade);
+        Self::update_stats(env, StatsScope::Vintage(credit.vintage_year), &trade);
+        
+        Ok(trade_id)
+    }
+
+    // Helper function to add a trade to a market's running totals and its daily bucket
+    fn update_stats(env: &Env, scope: StatsScope, trade: &Trade) {
+        let stats_key = DataKey::MarketStats(scope.clone(), trade.payment_token.clone());
+        let mut stats: MarketStats = env.storage().persistent().get(&stats_key).unwrap_or_default();
+        stats.last_price = trade.price_per_ton;
+        stats.total_volume_tons += trade.tons;
+        stats.total_value += trade.total_price;
+        stats.trade_count += 1;
+        stats.last_trade_at = trade.traded_at;
+        env.storage().persistent().set(&stats_key, &stats);
+        
+        let day_key = DataKey::DailyVolume(scope, trade.payment_token.clone(), trade.traded_at / SECONDS_PER_DAY);
+        let mut day: DailyVolume = env.storage().persistent().get(&day_key).unwrap_or_default();
+        day.tons += trade.tons;
+        day.value += trade.total_price;
+        env.storage().persistent().set(&day_key, &day);
+    }
+
+    // Helper function to add to a payee's aggregated payout
+    fn add_payout(payouts: &mut Map<Address, i128>, payee: &Address, amount: i128) {
+        if amount > 0 {
+            let current = payouts.get(payee.clone()).unwrap_or(0);
+            payouts.set(payee.clone(), current + amount);
+        }
+    }
+
+    // Helper function to transfer aggregated payouts from `payer`, one transfer per payee.
+    // The marketplace's share is kept and accrued as fees until withdrawn.
+    fn disburse(env: &Env, payment_token: &Address, payer: &Address, payouts: &Map<Address, i128>) {
+        let token_client = token::Client::new(env, payment_token);
+        let marketplace = env.current_contract_address();
+        
+        for (payee, amount) in payouts.iter() {
+            if payee == marketplace {
+                if *payer != marketplace {
+                    token_client.transfer(payer, &marketplace, &amount);
+                }
+                
+                Self::accrue_fees(env, payment_token, amount);
+            } else {
+                token_client.transfer(payer, &payee, &amount);
+            }
+        }
+    }
+
+    // Helper function to add to the fees held for withdrawal in a payment token
+    fn accrue_fees(env: &Env, payment_token: &Address, amount: i128) {
+        if amount > 0 {
+            let accrued = Self::get_accrued_fees(env.clone(), payment_token.clone());
+            env.storage().instance().set(&DataKey::AccruedFees(payment_token.clone()), &(accrued + amount));
+        }
+    }
+
+    // Helper function to load a payment token's fee
+    fn token_fee(env: &Env, payment_token: &Address) -> Result<u32, Error> {
+        env.storage()
+            .instance()
+            .get(&DataKey::TokenFee(payment_token.clone()))
+            .ok_or(Error::TokenNotAccepted)
+    }
+
+    // Helper function to ensure a payment token is open for new orders
+    fn require_accepted_token(env: &Env, payment_token: &Address) -> Result<(), Error> {
+        let accepted_tokens: Vec<Address> = env.storage()
+            .instance()
+            .get(&DataKey::AcceptedTokens)
+            .ok_or(Error::NotInitialized)?;
+        
+        if !accepted_tokens.contains(payment_token) {
+            return Err(Error::TokenNotAccepted);
+        }
+        
+        Ok(())
+    }
+
+    // Helper function to authenticate the admin
+    fn require_admin(env: &Env, admin: &Address) -> Result<(), Error> {
+        admin.require_auth();
+        
+        let stored_admin: Address = env.storage().instance().get(&DataKey::Admin).ok_or(Error::NotInitialized)?;
+        if *admin != stored_admin {
+            return Err(Error::NotAuthorized);
+        }
+        
+        Ok(())
+    }
+
+    // Helper function to carve `tons` out of a listing's escrowed credit once `remaining_tons`
+    // has been reduced, splitting it when the purchase is partial. The unsold remainder stays
+    // in escrow on the listing.
+    fn take_from_escrow(env: &Env, listing: &mut Listing, tons: u64) -> Result<u64, Error> {
+        if listing.remaining_tons == 0 {
+            return Ok(listing.credit_id);
+        }
+        
+        let amounts = Vec::from_array(env, [tons, listing.remaining_tons]);
+        let child_ids = Self::carbon_credit_client(env)?.split_credit(
+            &env.current_contract_address(),
+            &listing.credit_id,
+            &amounts,
+        );
+        
+        env.storage().persistent().remove(&DataKey::CreditListing(listing.credit_id));
+        listing.credit_id = child_ids.get_unchecked(1);
+        env.storage().persistent().set(&DataKey::CreditListing(listing.credit_id), &listing.listing_id);
+        
+        Ok(child_ids.get_unchecked(0))
+    }
+
+    // Helper function to mark a listing as mid-trade, rejecting re-entrant trades on it
+    fn lock_listing(env: &Env, listing_id: u64) -> Result<(), Error> {
+        let key = DataKey::TradeLock(listing_id);
+        if env.storage().temporary().has(&key) {
+            return Err(Error::TradeInProgress);
+        }
+        
+        env.storage().temporary().set(&key, &true);
+        
+        Ok(())
+    }
+
+    // Helper function to release a listing's trade lock
+    fn unlock_listing(env: &Env, listing_id: u64) {
+        env.storage().temporary().remove(&DataKey::TradeLock(listing_id));
+    }
+
+    // Helper function to build a client for the carbon credit contract
+    fn carbon_credit_client(env: &Env) -> Result<CarbonCreditClient<'_>, Error> {
+        let carbon_credit_contract: Address = env.storage()
+            .instance()
+            .get(&DataKey::CarbonCreditContract)
+            .ok_or(Error::NotInitialized)?;
+        
+        Ok(CarbonCreditClient::new(env, &carbon_credit_contract))
+    }
+
+    // Helper function to add an active listing to the active list and secondary indexes
+    fn index_listing(env: &Env, listing: &Listing) {
+        Self::add_to_index(env, &DataKey::ActiveListings, listing.listing_id);
+        Self::add_to_index(env, &DataKey::SellerListings(listing.seller.clone()), listing.listing_id);
+        Self::add_to_index(env, &DataKey::ProjectListings(listing.project_id.clone()), listing.listing_id);
+        Self::add_to_index(env, &DataKey::VintageListings(listing.vintage_year), listing.listing_id);
+    }
+
+    // Helper function to remove a listing from the active list and secondary indexes,
+    // releasing its credit for relisting
+    fn unindex_listing(env: &Env, listing: &Listing) {
+        env.storage().persistent().remove(&DataKey::CreditListing(listing.credit_id));
+        Self::remove_from_index(env, &DataKey::ActiveListings, listing.listing_id);
+        Self::remove_from_index(env, &DataKey::SellerListings(listing.seller.clone()), listing.listing_id);
+        Self::remove_from_index(env, &DataKey::ProjectListings(listing.project_id.clone()), listing.listing_id);
+        Self::remove_from_index(env, &DataKey::VintageListings(listing.vintage_year), listing.listing_id);
+    }
+
+    // Helper function to load a persistent listing index
+    fn index_ids(env: &Env, key: &DataKey) -> Vec<u64> {
+        env.storage().persistent().get(key).unwrap_or(Vec::new(env))
+    }
+
+    // Helper function to append a listing id to a persistent index
+    fn add_to_index(env: &Env, key: &DataKey, listing_id: u64) {
+        let mut ids = Self::index_ids(env, key);
+        ids.push_back(listing_id);
+        env.storage().persistent().set(key, &ids);
+    }
+
+    // Helper function to drop a listing id from a persistent index
+    fn remove_from_index(env: &Env, key: &DataKey, listing_id: u64) {
+        let mut ids = Self::index_ids(env, key);
+        if let Some(index) = ids.first_index_of(listing_id) {
+            ids.remove(index);
+            env.storage().persistent().set(key, &ids);
+        }
+    }
+
+    // Helper function to pick the narrowest index covering a filter
+    fn candidate_listings(env: &Env, filter: &ListingFilter) -> Result<Vec<u64>, Error> {
+        if let Some(seller) = &filter.seller {
+            return Ok(Self::index_ids(env, &DataKey::SellerListings(seller.clone())));
+        }
+        
+        if let Some(project_id) = &filter.project_id {
+            return Ok(Self::index_ids(env, &DataKey::ProjectListings(project_id.clone())));
+        }
+        
+        if let (Some(min_vintage), Some(max_vintage)) = (filter.min_vintage, filter.max_vintage) {
+            if max_vintage >= min_vintage && max_vintage - min_vintage < MAX_INDEXED_VINTAGE_SPAN {
+                let mut ids: Vec<u64> = Vec::new(env);
+                for vintage_year in min_vintage..=max_vintage {
+                    ids.append(&Self::index_ids(env, &DataKey::VintageListings(vintage_year)));
+                }
+                return Ok(ids);
+            }
+        }
+        
+        Self::get_active_listings(env.clone())
+    }
+
+    // Helper function to check an active listing against a filter; expired listings never match
+    fn listing_matches(env: &Env, listing: &Listing, filter: &ListingFilter) -> bool {
+        if Self::is_expired(env, listing) {
+            return false;
+        }
+        
+        if let Some(seller) = &filter.seller {
+            if *seller != listing.seller {
+                return false;
+            }
+        }
+        
+        if let Some(project_id) = &filter.project_id {
+            if *project_id != listing.project_id {
+                return false;
+            }
+        }
+        
+        if let Some(min_vintage) = filter.min_vintage {
+            if listing.vintage_year < min_vintage {
+                return false;
+            }
+        }
+        
+        if let Some(max_vintage) = filter.max_vintage {
+            if listing.vintage_year > max_vintage {
+                return false;
+            }
+        }
+        
+        let price_per_ton = Self::current_price(env, listing);
+        
+        if let Some(min_price) = filter.min_price {
+            if price_per_ton < min_price {
+                return false;
+            }
+        }
+        
+        if let Some(max_price) = filter.max_price {
+            if price_per_ton > max_price {
+                return false;
+            }
+        }
+        
+        true
+    }
+
+    // Helper function to remove bid from active bids
+    fn remove_from_active_bids(env: &Env, bid_id: u64) -> Result<(), Error> {
+        let active_bids: Vec<u64> = env.storage()
+            .persistent()
+            .get(&DataKey::ActiveBids)
+            .ok_or(Error::NotInitialized)?;
+        let mut new_active_bids: Vec<u64> = Vec::new(env);
+        
+        for id in active_bids.iter() {
+            if id != bid_id {
+                new_active_bids.push_back(id);
+            }
+        }
+        
+        env.storage().persistent().set(&DataKey::ActiveBids, &new_active_bids);
+        
+        Ok(())
     }
 }
 
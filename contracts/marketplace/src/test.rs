@@ -1,40 +1,105 @@
 #![cfg(test)]
 use super::*;
-use soroban_sdk::{testutils::Address as _, token, Address, Env};
+use carbon_credit::{CarbonCreditContract, CarbonCreditContractClient};
+use soroban_sdk::{contract, contractimpl, symbol_short, testutils::{Address as _, Ledger}, token, xdr::ToXdr, Address, Bytes, BytesN, Env, InvokeError, String};
 
 fn create_token_contract<'a>(env: &Env, admin: &Address) -> (token::Client<'a>, token::StellarAssetClient<'a>) {
     let token_contract_id = env.register_stellar_asset_contract_v2(admin.clone());
-    let client = token::Client::new(&env, &token_contract_id.address());
-    let admin_client = token::StellarAssetClient::new(&env, &token_contract_id.address());
+    let client = token::Client::new(env, &token_contract_id.address());
+    let admin_client = token::StellarAssetClient::new(env, &token_contract_id.address());
     (client, admin_client)
 }
 
+/// Payment token that tries to re-enter the marketplace from inside `transfer` and records
+/// whether the host aborted the nested call
+#[contract]
+pub struct ReentrantToken;
+
+#[contractimpl]
+impl ReentrantToken {
+    pub fn set_target(env: Env, marketplace: Address, attacker: Address, listing_id: u64) {
+        env.storage().instance().set(&symbol_short!("market"), &marketplace);
+        env.storage().instance().set(&symbol_short!("attacker"), &attacker);
+        env.storage().instance().set(&symbol_short!("listing"), &listing_id);
+    }
+
+    pub fn transfer(env: Env, _from: Address, _to: Address, _amount: i128) {
+        let marketplace: Address = env.storage().instance().get(&symbol_short!("market")).unwrap();
+        let attacker: Address = env.storage().instance().get(&symbol_short!("attacker")).unwrap();
+        let listing_id: u64 = env.storage().instance().get(&symbol_short!("listing")).unwrap();
+        
+        let calls: u32 = env.storage().instance().get(&symbol_short!("calls")).unwrap_or(0);
+        env.storage().instance().set(&symbol_short!("calls"), &(calls + 1));
+        
+        let result = MarketplaceContractClient::new(&env, &marketplace).try_buy_credit(&attacker, &listing_id, &100, &i128::MAX);
+        if result == Err(Err(InvokeError::Abort)) {
+            env.storage().instance().set(&symbol_short!("aborted"), &true);
+        }
+    }
+
+    pub fn calls(env: Env) -> u32 {
+        env.storage().instance().get(&symbol_short!("calls")).unwrap_or(0)
+    }
+
+    pub fn aborted(env: Env) -> bool {
+        env.storage().instance().get(&symbol_short!("aborted")).unwrap_or(false)
+    }
+}
+
+fn create_carbon_credit_contract<'a>(env: &Env, admin: &Address) -> CarbonCreditContractClient<'a> {
+    let contract_id = env.register(CarbonCreditContract, ());
+    let client = CarbonCreditContractClient::new(env, &contract_id);
+    client.initialize(admin, &Address::generate(env));
+    client
+}
+
+fn issue_credit(env: &Env, carbon_client: &CarbonCreditContractClient, issuer: &Address, amount_tons: u64) -> u64 {
+    carbon_client.issue_credit(
+        issuer,
+        &String::from_str(env, "PROJ-001"),
+        &String::from_str(env, "Solar Farm Project"),
+        &2024,
+        &amount_tons,
+        &carbon_credit::Royalty::default(),
+    )
+}
+
+fn issue_verified_credit(env: &Env, carbon_client: &CarbonCreditContractClient, issuer: &Address, amount_tons: u64) -> u64 {
+    let credit_id = issue_credit(env, carbon_client, issuer, amount_tons);
+    verify_credit(carbon_client, credit_id, &Address::generate(env));
+    credit_id
+}
+
 #[test]
 fn test_create_listing() {
     let env = Env::default();
-    let contract_id = env.register_contract(None, MarketplaceContract);
+    let contract_id = env.register(MarketplaceContract, ());
     let client = MarketplaceContractClient::new(&env, &contract_id);
     
     let admin = Address::generate(&env);
     let seller = Address::generate(&env);
-    let carbon_credit_contract = Address::generate(&env);
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
     let payment_token = Address::generate(&env);
     
     // Initialize marketplace
-    client.initialize(&admin, &carbon_credit_contract, &payment_token, &250);
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &payment_token, &250);
     
     // Create listing
     env.mock_all_auths();
-    let listing_id = client.create_listing(&seller, &1, &100, &500);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    let listing_id = client.create_listing(&seller, &credit_id, &payment_token, &100, &500, &None);
     
     assert_eq!(listing_id, 1);
     
     // Get listing
     let listing = client.get_listing(&listing_id);
-    assert_eq!(listing.credit_id, 1);
+    assert_eq!(listing.credit_id, credit_id);
     assert_eq!(listing.price_per_ton, 100);
     assert_eq!(listing.amount_tons, 500);
     assert_eq!(listing.status, ListingStatus::Active);
+    
+    // Credit is held in marketplace escrow
+    assert_eq!(carbon_client.get_owner(&credit_id), Some(contract_id));
 }
 
 #[test]
@@ -53,48 +118,54 @@ fn test_buy_credit() {
     // Mint tokens to buyer
     token_admin.mint(&buyer, &100000);
     
-    let carbon_credit_contract = Address::generate(&env);
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
     
     // Create marketplace
-    let contract_id = env.register_contract(None, MarketplaceContract);
+    let contract_id = env.register(MarketplaceContract, ());
     let client = MarketplaceContractClient::new(&env, &contract_id);
     
-    client.initialize(&admin, &carbon_credit_contract, &token_address, &250);
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_address, &250);
     
     // Create listing
-    let listing_id = client.create_listing(&seller, &1, &100, &500);
+    let listing_id = client.create_listing(&seller, &credit_id, &token_address, &100, &500, &None);
     
     // Buy credit
-    client.buy_credit(&buyer, &listing_id);
+    client.buy_credit(&buyer, &listing_id, &500, &100);
     
     // Check listing status
     let listing = client.get_listing(&listing_id);
     assert_eq!(listing.status, ListingStatus::Sold);
     
+    // Check buyer received the credit
+    assert_eq!(carbon_client.get_owner(&credit_id), Some(buyer));
+    
     // Check seller balance (50000 - 2.5% fee = 48750)
     let seller_balance = token_client.balance(&seller);
     assert_eq!(seller_balance, 48750);
     
-    // Check admin received fee
-    let admin_balance = token_client.balance(&admin);
-    assert_eq!(admin_balance, 1250);
+    // Check the fee accrued in the marketplace
+    assert_eq!(client.get_accrued_fees(&token_address), 1250);
+    assert_eq!(token_client.balance(&contract_id), 1250);
+    assert_eq!(token_client.balance(&admin), 0);
 }
 
 #[test]
 fn test_cancel_listing() {
     let env = Env::default();
-    let contract_id = env.register_contract(None, MarketplaceContract);
+    let contract_id = env.register(MarketplaceContract, ());
     let client = MarketplaceContractClient::new(&env, &contract_id);
     
     let admin = Address::generate(&env);
     let seller = Address::generate(&env);
-    let carbon_credit_contract = Address::generate(&env);
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
     let payment_token = Address::generate(&env);
     
-    client.initialize(&admin, &carbon_credit_contract, &payment_token, &250);
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &payment_token, &250);
     
     env.mock_all_auths();
-    let listing_id = client.create_listing(&seller, &1, &100, &500);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    let listing_id = client.create_listing(&seller, &credit_id, &payment_token, &100, &500, &None);
     
     // Cancel listing
     client.cancel_listing(&seller, &listing_id);
@@ -102,23 +173,27 @@ fn test_cancel_listing() {
     // Check status
     let listing = client.get_listing(&listing_id);
     assert_eq!(listing.status, ListingStatus::Cancelled);
+    
+    // Check credit was returned to the seller
+    assert_eq!(carbon_client.get_owner(&credit_id), Some(seller));
 }
 
 #[test]
 fn test_update_price() {
     let env = Env::default();
-    let contract_id = env.register_contract(None, MarketplaceContract);
+    let contract_id = env.register(MarketplaceContract, ());
     let client = MarketplaceContractClient::new(&env, &contract_id);
     
     let admin = Address::generate(&env);
     let seller = Address::generate(&env);
-    let carbon_credit_contract = Address::generate(&env);
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
     let payment_token = Address::generate(&env);
     
-    client.initialize(&admin, &carbon_credit_contract, &payment_token, &250);
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &payment_token, &250);
     
     env.mock_all_auths();
-    let listing_id = client.create_listing(&seller, &1, &100, &500);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    let listing_id = client.create_listing(&seller, &credit_id, &payment_token, &100, &500, &None);
     
     // Update price
     client.update_price(&seller, &listing_id, &150);
@@ -126,4 +201,1254 @@ fn test_update_price() {
     // Check new price
     let listing = client.get_listing(&listing_id);
     assert_eq!(listing.price_per_ton, 150);
+    
+    // A buyer who saw the old price is protected from the increase
+    let buyer = Address::generate(&env);
+    assert_eq!(
+        client.try_buy_credit(&buyer, &listing_id, &100, &100),
+        Err(Ok(Error::PriceLimitExceeded))
+    );
+}
+
+#[test]
+fn test_error_codes() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let stranger = Address::generate(&env);
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let payment_token = Address::generate(&env);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &payment_token, &250);
+    
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    assert_eq!(
+        client.try_create_listing(&seller, &credit_id, &payment_token, &0, &500, &None),
+        Err(Ok(Error::InvalidPrice))
+    );
+    let listing_id = client.create_listing(&seller, &credit_id, &payment_token, &100, &500, &None);
+    
+    assert_eq!(client.try_get_listing(&42).err(), Some(Ok(Error::ListingNotFound)));
+    assert_eq!(
+        client.try_cancel_listing(&stranger, &listing_id),
+        Err(Ok(Error::NotSeller))
+    );
+    
+    client.cancel_listing(&seller, &listing_id);
+    assert_eq!(
+        client.try_update_price(&seller, &listing_id, &150),
+        Err(Ok(Error::ListingNotActive))
+    );
+    
+    assert_eq!(
+        client.try_update_marketplace_fee(&stranger, &payment_token, &100),
+        Err(Ok(Error::NotAuthorized))
+    );
+    assert_eq!(
+        client.try_update_marketplace_fee(&admin, &payment_token, &1001),
+        Err(Ok(Error::FeeTooHigh))
+    );
+}
+
+#[test]
+fn test_partial_fill() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&buyer, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_client.address, &250);
+    
+    let listing_id = client.create_listing(&seller, &credit_id, &token_client.address, &100, &500, &None);
+    
+    // Cannot buy more than what is left
+    assert_eq!(
+        client.try_buy_credit(&buyer, &listing_id, &501, &100),
+        Err(Ok(Error::InvalidAmount))
+    );
+    
+    // Buy 37 tons out of 500
+    client.buy_credit(&buyer, &listing_id, &37, &100);
+    
+    let listing = client.get_listing(&listing_id);
+    assert_eq!(listing.status, ListingStatus::Active);
+    assert_eq!(listing.remaining_tons, 463);
+    
+    let fills = client.get_listing_fills(&listing_id);
+    assert_eq!(fills.len(), 1);
+    let fill = fills.get_unchecked(0);
+    assert_eq!(fill.tons, 37);
+    assert_eq!(fill.total_price, 3700);
+    
+    // Buyer holds a 37 ton child credit, the remainder stays in escrow
+    let bought = carbon_client.get_credit(&fill.credit_id);
+    assert_eq!(bought.amount_tons, 37);
+    assert_eq!(carbon_client.get_owner(&fill.credit_id), Some(buyer.clone()));
+    assert_eq!(carbon_client.get_credit(&listing.credit_id).amount_tons, 463);
+    assert_eq!(carbon_client.get_owner(&listing.credit_id), Some(contract_id));
+    
+    // 3700 - 2.5% fee
+    assert_eq!(token_client.balance(&seller), 3608);
+    assert_eq!(client.get_accrued_fees(&token_client.address), 92);
+    
+    // Buying the remainder exhausts the listing
+    client.buy_credit(&buyer, &listing_id, &463, &100);
+    
+    let listing = client.get_listing(&listing_id);
+    assert_eq!(listing.status, ListingStatus::Sold);
+    assert_eq!(listing.remaining_tons, 0);
+    assert_eq!(client.get_listing_fills(&listing_id).len(), 2);
+    assert_eq!(carbon_client.get_owner(&listing.credit_id), Some(buyer));
+    assert_eq!(client.get_active_listings().len(), 0);
+}
+
+fn verify_credit(carbon_client: &CarbonCreditContractClient, credit_id: u64, verifier: &Address) {
+    carbon_client.update_verification(&credit_id, &carbon_credit::VerificationStatus::Verified, verifier);
+}
+
+#[test]
+fn test_fill_bid() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&buyer, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let credit_id = issue_credit(&env, &carbon_client, &seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_client.address, &250);
+    
+    // Bid for 300 tons of 2020+ vintages at up to 120 per ton
+    let filter = BidFilter {
+        project_id: None,
+        min_vintage: Some(2020),
+        max_vintage: None,
+    };
+    let bid_id = client.create_bid(&buyer, &token_client.address, &120, &300, &filter);
+    assert_eq!(token_client.balance(&buyer), 64000);
+    assert_eq!(token_client.balance(&contract_id), 36000);
+    
+    // Unverified, missing and other people's credits are not accepted
+    assert_eq!(
+        client.try_fill_bid(&seller, &bid_id, &credit_id, &200),
+        Err(Ok(Error::NotVerified))
+    );
+    verify_credit(&carbon_client, credit_id, &Address::generate(&env));
+    assert_eq!(
+        client.try_fill_bid(&seller, &bid_id, &999, &200),
+        Err(Ok(Error::CreditNotFound))
+    );
+    assert_eq!(
+        client.try_fill_bid(&Address::generate(&env), &bid_id, &credit_id, &200),
+        Err(Ok(Error::NotCreditOwner))
+    );
+    assert_eq!(client.get_bid(&bid_id).remaining_tons, 300);
+    
+    // Sell 200 tons into the bid, splitting them off the seller's credit
+    let delivered_id = client.fill_bid(&seller, &bid_id, &credit_id, &200);
+    assert_eq!(carbon_client.get_owner(&delivered_id), Some(buyer.clone()));
+    assert_eq!(carbon_client.get_credit(&delivered_id).amount_tons, 200);
+    assert_eq!(carbon_client.balance_tons(&seller), 300);
+    
+    // 24000 - 2.5% fee
+    assert_eq!(token_client.balance(&seller), 23400);
+    assert_eq!(client.get_accrued_fees(&token_client.address), 600);
+    
+    let bid = client.get_bid(&bid_id);
+    assert_eq!(bid.remaining_tons, 100);
+    assert_eq!(bid.status, BidStatus::Active);
+    
+    // Cancelling refunds the unfilled escrow
+    client.cancel_bid(&buyer, &bid_id);
+    assert_eq!(client.get_bid(&bid_id).status, BidStatus::Cancelled);
+    assert_eq!(token_client.balance(&buyer), 76000);
+    assert_eq!(token_client.balance(&contract_id), 600);
+    assert_eq!(client.get_active_bids().len(), 0);
+}
+
+#[test]
+fn test_match_orders() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&buyer, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let credit_id = issue_credit(&env, &carbon_client, &seller, 500);
+    verify_credit(&carbon_client, credit_id, &Address::generate(&env));
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_client.address, &250);
+    
+    let listing_id = client.create_listing(&seller, &credit_id, &token_client.address, &100, &500, &None);
+    
+    // A bid below the ask does not cross
+    let filter = BidFilter {
+        project_id: Some(String::from_str(&env, "PROJ-001")),
+        min_vintage: None,
+        max_vintage: None,
+    };
+    let low_bid_id = client.create_bid(&buyer, &token_client.address, &90, &100, &filter);
+    assert_eq!(
+        client.try_match_orders(&low_bid_id, &listing_id),
+        Err(Ok(Error::PriceNotMatched))
+    );
+    client.cancel_bid(&buyer, &low_bid_id);
+    
+    // A bid for another project does not match
+    let other_filter = BidFilter {
+        project_id: Some(String::from_str(&env, "PROJ-002")),
+        min_vintage: None,
+        max_vintage: None,
+    };
+    let other_bid_id = client.create_bid(&buyer, &token_client.address, &150, &100, &other_filter);
+    assert_eq!(
+        client.try_match_orders(&other_bid_id, &listing_id),
+        Err(Ok(Error::CreditNotAccepted))
+    );
+    client.cancel_bid(&buyer, &other_bid_id);
+    
+    // 200 tons at up to 150 cross the 100 ask and trade at the ask
+    let bid_id = client.create_bid(&buyer, &token_client.address, &150, &200, &filter);
+    assert_eq!(client.match_orders(&bid_id, &listing_id), 200);
+    
+    assert_eq!(client.get_bid(&bid_id).status, BidStatus::Filled);
+    let listing = client.get_listing(&listing_id);
+    assert_eq!(listing.remaining_tons, 300);
+    
+    let fill = client.get_listing_fills(&listing_id).get_unchecked(0);
+    assert_eq!(fill.buyer, buyer);
+    assert_eq!(fill.price_per_ton, 100);
+    assert_eq!(carbon_client.get_owner(&fill.credit_id), Some(buyer.clone()));
+    
+    // Buyer paid 20000 and got the 10000 price improvement back
+    assert_eq!(token_client.balance(&buyer), 80000);
+    assert_eq!(token_client.balance(&seller), 19500);
+    assert_eq!(client.get_accrued_fees(&token_client.address), 500);
+    assert_eq!(token_client.balance(&contract_id), 500);
+}
+
+#[test]
+fn test_english_auction() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().set_timestamp(1000);
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&alice, &100000);
+    token_admin.mint(&bob, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let pending_id = issue_credit(&env, &carbon_client, &seller, 500);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_client.address, &250);
+    
+    assert_eq!(
+        client.try_create_auction(&seller, &credit_id, &token_client.address, &40000, &1000, &1000),
+        Err(Ok(Error::InvalidEndTime))
+    );
+    assert_eq!(
+        client.try_create_auction(&seller, &pending_id, &token_client.address, &40000, &1000, &2000),
+        Err(Ok(Error::NotVerified))
+    );
+    assert_eq!(
+        client.try_create_auction(&seller, &42, &token_client.address, &40000, &1000, &2000),
+        Err(Ok(Error::CreditNotFound))
+    );
+    
+    let auction_id = client.create_auction(&seller, &credit_id, &token_client.address, &40000, &1000, &2000);
+    assert_eq!(carbon_client.get_owner(&credit_id), Some(contract_id.clone()));
+    assert_eq!(client.get_auction(&auction_id).amount_tons, 500);
+    
+    // Opening bid must meet the reserve
+    assert_eq!(
+        client.try_place_bid(&alice, &auction_id, &39999),
+        Err(Ok(Error::BidTooLow))
+    );
+    client.place_bid(&alice, &auction_id, &40000);
+    assert_eq!(token_client.balance(&alice), 60000);
+    
+    // Later bids must beat the highest by the increment; Alice is refunded when outbid
+    assert_eq!(
+        client.try_place_bid(&bob, &auction_id, &40500),
+        Err(Ok(Error::BidTooLow))
+    );
+    client.place_bid(&bob, &auction_id, &41000);
+    assert_eq!(token_client.balance(&alice), 100000);
+    assert_eq!(token_client.balance(&bob), 59000);
+    assert_eq!(token_client.balance(&contract_id), 41000);
+    
+    assert_eq!(
+        client.try_settle_auction(&auction_id),
+        Err(Ok(Error::AuctionNotEnded))
+    );
+    
+    env.ledger().set_timestamp(2000);
+    assert_eq!(
+        client.try_place_bid(&alice, &auction_id, &50000),
+        Err(Ok(Error::AuctionEnded))
+    );
+    
+    client.settle_auction(&auction_id);
+    
+    let auction = client.get_auction(&auction_id);
+    assert_eq!(auction.status, AuctionStatus::Sold);
+    assert_eq!(auction.highest_bidder, Some(bob.clone()));
+    assert_eq!(carbon_client.get_owner(&credit_id), Some(bob));
+    
+    // 41000 - 2.5% fee
+    assert_eq!(token_client.balance(&seller), 39975);
+    assert_eq!(client.get_accrued_fees(&token_client.address), 1025);
+    assert_eq!(token_client.balance(&contract_id), 1025);
+    
+    assert_eq!(
+        client.try_settle_auction(&auction_id),
+        Err(Ok(Error::AuctionNotActive))
+    );
+}
+
+#[test]
+fn test_dutch_listing() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().set_timestamp(1000);
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&buyer, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    let stepped_credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_client.address, &250);
+    
+    let terms = |step_seconds: u64| DutchTerms { floor_price: 100, duration: 1000, step_seconds };
+    assert_eq!(
+        client.try_create_dutch_listing(&seller, &credit_id, &token_client.address, &200, &500, &terms(2000)),
+        Err(Ok(Error::InvalidSchedule))
+    );
+    
+    // 200 down to 100 over 1000 seconds, linearly and in 300 second steps
+    let listing_id = client.create_dutch_listing(&seller, &credit_id, &token_client.address, &200, &500, &terms(0));
+    let stepped_id = client.create_dutch_listing(&seller, &stepped_credit_id, &token_client.address, &200, &500, &terms(300));
+    
+    assert_eq!(client.get_current_price(&listing_id), 200);
+    
+    env.ledger().set_timestamp(1500);
+    assert_eq!(client.get_current_price(&listing_id), 150);
+    assert_eq!(client.get_current_price(&stepped_id), 170);
+    
+    assert_eq!(
+        client.try_update_price(&seller, &listing_id, &300),
+        Err(Ok(Error::NotFixedPrice))
+    );
+    
+    // Purchases are charged the price in force at execution
+    client.buy_credit(&buyer, &listing_id, &10, &150);
+    assert_eq!(client.get_listing_fills(&listing_id).get_unchecked(0).price_per_ton, 150);
+    assert_eq!(token_client.balance(&buyer), 98500);
+    
+    // The price rests at the floor once the schedule has run out
+    env.ledger().set_timestamp(5000);
+    assert_eq!(client.get_current_price(&listing_id), 100);
+    assert_eq!(client.get_current_price(&stepped_id), 100);
+    
+    client.buy_credit(&buyer, &stepped_id, &10, &100);
+    assert_eq!(token_client.balance(&buyer), 97500);
+}
+
+fn commitment(env: &Env, auction_id: u64, bidder: &Address, amount: i128, salt: &BytesN<32>) -> BytesN<32> {
+    let mut payload = Bytes::from_array(env, &auction_id.to_be_bytes());
+    payload.append(&bidder.clone().to_xdr(env));
+    payload.extend_from_array(&amount.to_be_bytes());
+    payload.extend_from_array(&salt.to_array());
+    env.crypto().sha256(&payload).to_bytes()
+}
+
+#[test]
+fn test_sealed_bid_auction() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().set_timestamp(1000);
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let carol = Address::generate(&env);
+    let copycat = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&alice, &100000);
+    token_admin.mint(&bob, &100000);
+    token_admin.mint(&carol, &100000);
+    token_admin.mint(&copycat, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let pending_id = issue_credit(&env, &carbon_client, &seller, 500);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    let treasury = Address::generate(&env);
+    client.initialize(&admin, &treasury, &carbon_client.address, &token_client.address, &0);
+    
+    assert_eq!(
+        client.try_create_sealed_auction(&Address::generate(&env), &credit_id, &token_client.address, &40000, &1000, &2000, &3000),
+        Err(Ok(Error::NotCreditOwner))
+    );
+    assert_eq!(
+        client.try_create_sealed_auction(&seller, &pending_id, &token_client.address, &40000, &1000, &2000, &3000),
+        Err(Ok(Error::NotVerified))
+    );
+    
+    // Commits until 2000, reveals until 3000, 1000 deposit
+    let auction_id = client.create_sealed_auction(&seller, &credit_id, &token_client.address, &40000, &1000, &2000, &3000);
+    
+    let alice_salt = BytesN::from_array(&env, &[1; 32]);
+    let bob_salt = BytesN::from_array(&env, &[2; 32]);
+    let carol_salt = BytesN::from_array(&env, &[3; 32]);
+    let bob_commitment = commitment(&env, auction_id, &bob, 50000, &bob_salt);
+    client.commit_bid(&alice, &auction_id, &commitment(&env, auction_id, &alice, 45000, &alice_salt));
+    client.commit_bid(&bob, &auction_id, &bob_commitment);
+    client.commit_bid(&carol, &auction_id, &commitment(&env, auction_id, &carol, 60000, &carol_salt));
+    
+    // Commitments are public; a copy of Bob's is accepted but can never be revealed by anyone else
+    client.commit_bid(&copycat, &auction_id, &bob_commitment);
+    assert_eq!(token_client.balance(&contract_id), 4000);
+    
+    assert_eq!(
+        client.try_commit_bid(&alice, &auction_id, &commitment(&env, auction_id, &alice, 1, &alice_salt)),
+        Err(Ok(Error::AlreadyCommitted))
+    );
+    assert_eq!(
+        client.try_reveal_bid(&alice, &auction_id, &45000, &alice_salt),
+        Err(Ok(Error::NotRevealPhase))
+    );
+    
+    env.ledger().set_timestamp(2000);
+    assert_eq!(
+        client.try_commit_bid(&seller, &auction_id, &commitment(&env, auction_id, &seller, 1, &alice_salt)),
+        Err(Ok(Error::NotCommitPhase))
+    );
+    
+    // A reveal must match the commitment
+    assert_eq!(
+        client.try_reveal_bid(&bob, &auction_id, &55000, &bob_salt),
+        Err(Ok(Error::CommitmentMismatch))
+    );
+    assert_eq!(
+        client.try_reveal_bid(&copycat, &auction_id, &50000, &bob_salt),
+        Err(Ok(Error::CommitmentMismatch))
+    );
+    client.reveal_bid(&alice, &auction_id, &45000, &alice_salt);
+    client.reveal_bid(&bob, &auction_id, &50000, &bob_salt);
+    
+    // Alice was refunded when Bob took the lead; Carol and the copycat never reveal
+    assert_eq!(token_client.balance(&alice), 99000);
+    assert_eq!(token_client.balance(&bob), 49000);
+    
+    assert_eq!(
+        client.try_finalize(&auction_id),
+        Err(Ok(Error::AuctionNotEnded))
+    );
+    
+    env.ledger().set_timestamp(3000);
+    client.finalize(&auction_id);
+    
+    let auction = client.get_sealed_auction(&auction_id);
+    assert_eq!(auction.status, AuctionStatus::Sold);
+    assert_eq!(auction.highest_bidder, Some(bob.clone()));
+    assert_eq!(carbon_client.get_owner(&credit_id), Some(bob.clone()));
+    
+    // Revealed deposits are returned, unrevealed ones are forfeited to the accrued fees
+    assert_eq!(token_client.balance(&alice), 100000);
+    assert_eq!(token_client.balance(&bob), 50000);
+    assert_eq!(token_client.balance(&carol), 99000);
+    assert_eq!(token_client.balance(&copycat), 99000);
+    assert_eq!(token_client.balance(&seller), 50000);
+    assert_eq!(client.get_accrued_fees(&token_client.address), 2000);
+    assert_eq!(token_client.balance(&contract_id), 2000);
+    
+    client.withdraw_fees(&admin, &token_client.address, &2000);
+    assert_eq!(token_client.balance(&treasury), 2000);
+}
+
+#[test]
+fn test_listing_expiry() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().set_timestamp(1000);
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&buyer, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let expiring_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    let open_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_client.address, &250);
+    
+    assert_eq!(
+        client.try_create_listing(&seller, &expiring_id, &token_client.address, &100, &500, &Some(1000)),
+        Err(Ok(Error::InvalidEndTime))
+    );
+    let expiring_listing = client.create_listing(&seller, &expiring_id, &token_client.address, &100, &500, &Some(2000));
+    let open_listing = client.create_listing(&seller, &open_id, &token_client.address, &100, &500, &None);
+    
+    // Partially sold before expiry
+    client.buy_credit(&buyer, &expiring_listing, &100, &100);
+    
+    env.ledger().set_timestamp(2000);
+    assert_eq!(
+        client.try_buy_credit(&buyer, &expiring_listing, &100, &100),
+        Err(Ok(Error::ListingExpired))
+    );
+    
+    // Expired listings drop out of queries even before they are pruned
+    let page = client.get_listings(&0, &10);
+    assert_eq!(page.listings.len(), 1);
+    assert_eq!(page.listings.get_unchecked(0).listing_id, open_listing);
+    
+    // Scanning one listing prunes it; the cursor stays put since the index shifted down
+    assert_eq!(client.prune_expired(&0, &1), PruneResult { pruned: 1, next_start: Some(0) });
+    
+    let listing = client.get_listing(&expiring_listing);
+    assert_eq!(listing.status, ListingStatus::Expired);
+    assert_eq!(carbon_client.get_owner(&listing.credit_id), Some(seller.clone()));
+    assert_eq!(carbon_client.get_credit(&listing.credit_id).amount_tons, 400);
+    
+    // Listings without an expiry stay active
+    assert_eq!(client.get_active_listings(), soroban_sdk::vec![&env, open_listing]);
+    assert_eq!(client.prune_expired(&0, &10), PruneResult { pruned: 0, next_start: None });
+}
+
+#[test]
+fn test_multiple_payment_tokens() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (usdc_client, usdc_admin) = create_token_contract(&env, &admin);
+    let (eurc_client, eurc_admin) = create_token_contract(&env, &admin);
+    let (other_client, _) = create_token_contract(&env, &admin);
+    usdc_admin.mint(&buyer, &100000);
+    eurc_admin.mint(&buyer, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let usd_credit_id = issue_verified_credit(&env, &carbon_client, &seller, 100);
+    let eur_credit_id = issue_verified_credit(&env, &carbon_client, &seller, 100);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &usdc_client.address, &250);
+    
+    // Unlisted tokens are rejected for new orders
+    assert_eq!(
+        client.try_create_listing(&seller, &eur_credit_id, &eurc_client.address, &100, &100, &None),
+        Err(Ok(Error::TokenNotAccepted))
+    );
+    
+    client.add_payment_token(&admin, &eurc_client.address, &100);
+    assert_eq!(
+        client.try_add_payment_token(&admin, &eurc_client.address, &100),
+        Err(Ok(Error::TokenAlreadyAccepted))
+    );
+    assert_eq!(
+        client.get_payment_tokens(),
+        soroban_sdk::vec![&env, usdc_client.address.clone(), eurc_client.address.clone()]
+    );
+    assert_eq!(client.get_marketplace_fee(&eurc_client.address), 100);
+    assert_eq!(
+        client.try_get_marketplace_fee(&other_client.address),
+        Err(Ok(Error::TokenNotAccepted))
+    );
+    
+    let usd_listing = client.create_listing(&seller, &usd_credit_id, &usdc_client.address, &100, &100, &None);
+    let eur_listing = client.create_listing(&seller, &eur_credit_id, &eurc_client.address, &90, &100, &None);
+    assert_eq!(client.get_listing(&eur_listing).payment_token, eurc_client.address);
+    
+    // Each listing settles in its own token at that token's fee
+    client.buy_credit(&buyer, &usd_listing, &100, &100);
+    client.buy_credit(&buyer, &eur_listing, &100, &90);
+    
+    assert_eq!(usdc_client.balance(&seller), 9750);
+    assert_eq!(client.get_accrued_fees(&usdc_client.address), 250);
+    assert_eq!(eurc_client.balance(&seller), 8910);
+    assert_eq!(client.get_accrued_fees(&eurc_client.address), 90);
+    
+    // Removed tokens can no longer be used for new orders
+    client.remove_payment_token(&admin, &eurc_client.address);
+    assert_eq!(
+        client.try_create_bid(&buyer, &eurc_client.address, &100, &10, &BidFilter {
+            project_id: None,
+            min_vintage: None,
+            max_vintage: None,
+        }),
+        Err(Ok(Error::TokenNotAccepted))
+    );
+}
+
+#[test]
+fn test_fee_withdrawal() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    let treasury = Address::generate(&env);
+    let stranger = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&buyer, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &treasury, &carbon_client.address, &token_client.address, &250);
+    assert_eq!(client.get_treasury(), treasury);
+    
+    let listing_id = client.create_listing(&seller, &credit_id, &token_client.address, &100, &500, &None);
+    client.buy_credit(&buyer, &listing_id, &200, &100);
+    client.buy_credit(&buyer, &listing_id, &300, &100);
+    assert_eq!(client.get_accrued_fees(&token_client.address), 1250);
+    
+    assert_eq!(
+        client.try_withdraw_fees(&stranger, &token_client.address, &100),
+        Err(Ok(Error::NotAuthorized))
+    );
+    assert_eq!(
+        client.try_withdraw_fees(&admin, &token_client.address, &1251),
+        Err(Ok(Error::InsufficientFees))
+    );
+    
+    // Withdrawals always go to the treasury, which the admin can move
+    client.withdraw_fees(&admin, &token_client.address, &1000);
+    assert_eq!(client.get_accrued_fees(&token_client.address), 250);
+    assert_eq!(token_client.balance(&treasury), 1000);
+    assert_eq!(token_client.balance(&contract_id), 250);
+    
+    let new_treasury = Address::generate(&env);
+    client.set_treasury(&admin, &new_treasury);
+    client.withdraw_fees(&admin, &token_client.address, &250);
+    assert_eq!(token_client.balance(&new_treasury), 250);
+    assert_eq!(token_client.balance(&admin), 0);
+}
+
+#[test]
+fn test_resale_royalties() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let issuer = Address::generate(&env);
+    let verifier = Address::generate(&env);
+    let trader = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&trader, &100000);
+    token_admin.mint(&buyer, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let credit_id = carbon_client.issue_credit(
+        &issuer,
+        &String::from_str(&env, "PROJ-001"),
+        &String::from_str(&env, "Solar Farm Project"),
+        &2024,
+        &100,
+        &carbon_credit::Royalty { issuer_bps: 500, verifier_bps: 100 },
+    );
+    verify_credit(&carbon_client, credit_id, &verifier);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_client.address, &250);
+    
+    // Primary sale by the issuer pays no royalties
+    let listing_id = client.create_listing(&issuer, &credit_id, &token_client.address, &100, &100, &None);
+    client.buy_credit(&trader, &listing_id, &100, &100);
+    assert_eq!(token_client.balance(&issuer), 9750);
+    assert_eq!(token_client.balance(&verifier), 0);
+    
+    // Resale pays 5% to the issuer and 1% to the verifier
+    let resale_id = client.create_listing(&trader, &credit_id, &token_client.address, &200, &100, &None);
+    client.buy_credit(&buyer, &resale_id, &100, &200);
+    
+    assert_eq!(token_client.balance(&issuer), 10750);
+    assert_eq!(token_client.balance(&verifier), 200);
+    assert_eq!(client.get_accrued_fees(&token_client.address), 750);
+    
+    // 20000 - 500 fee - 1000 issuer royalty - 200 verifier share
+    assert_eq!(token_client.balance(&trader), 90000 + 18300);
+}
+
+#[test]
+fn test_buy_and_retire() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&buyer, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let rejected_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    let credit_id = issue_credit(&env, &carbon_client, &seller, 500);
+    verify_credit(&carbon_client, credit_id, &Address::generate(&env));
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_client.address, &250);
+    
+    let beneficiary = String::from_str(&env, "Acme Corp");
+    let reason = String::from_str(&env, "2024 Scope 1 offset");
+    let reporting_period = String::from_str(&env, "FY2024");
+    
+    // Credits whose verification is revoked after listing are rejected before any payment
+    let rejected_listing = client.create_listing(&seller, &rejected_id, &token_client.address, &100, &500, &None);
+    carbon_client.update_verification(&rejected_id, &carbon_credit::VerificationStatus::Rejected, &Address::generate(&env));
+    assert_eq!(
+        client.try_buy_and_retire(&buyer, &rejected_listing, &100, &100, &beneficiary, &reason, &reporting_period),
+        Err(Ok(Error::NotVerified))
+    );
+    assert_eq!(token_client.balance(&buyer), 100000);
+    
+    let listing_id = client.create_listing(&seller, &credit_id, &token_client.address, &100, &500, &None);
+    let retirement_id = client.buy_and_retire(&buyer, &listing_id, &100, &100, &beneficiary, &reason, &reporting_period);
+    
+    // The purchased tons are retired in the buyer's name and never sit in their wallet
+    let record = carbon_client.get_retirement(&retirement_id);
+    assert_eq!(record.owner, buyer);
+    assert_eq!(record.amount_tons, 100);
+    assert_eq!(record.beneficiary, beneficiary);
+    assert_eq!(record.reporting_period, reporting_period);
+    
+    assert_eq!(carbon_client.get_owner(&record.credit_id), None);
+    assert_eq!(carbon_client.balance_tons(&buyer), 0);
+    
+    assert_eq!(token_client.balance(&buyer), 90000);
+    assert_eq!(client.get_listing(&listing_id).remaining_tons, 400);
+}
+
+#[test]
+fn test_buy_many() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let other_seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    let (other_token_client, _) = create_token_contract(&env, &admin);
+    token_admin.mint(&buyer, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let first_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    let second_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    let third_id = issue_verified_credit(&env, &carbon_client, &other_seller, 500);
+    let foreign_id = issue_verified_credit(&env, &carbon_client, &other_seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_client.address, &250);
+    client.add_payment_token(&admin, &other_token_client.address, &250);
+    
+    let first = client.create_listing(&seller, &first_id, &token_client.address, &100, &500, &None);
+    let second = client.create_listing(&seller, &second_id, &token_client.address, &120, &500, &None);
+    let third = client.create_listing(&other_seller, &third_id, &token_client.address, &80, &500, &None);
+    let foreign = client.create_listing(&other_seller, &foreign_id, &other_token_client.address, &80, &500, &None);
+    
+    let cart = soroban_sdk::vec![&env, (first, 100u64), (second, 50u64), (third, 200u64)];
+    
+    // 10000 + 6000 + 16000 = 32000 exceeds the cap, nothing is filled
+    assert_eq!(
+        client.try_buy_many(&buyer, &cart, &31999),
+        Err(Ok(Error::PriceLimitExceeded))
+    );
+    assert_eq!(client.get_listing(&first).remaining_tons, 500);
+    
+    // Listings priced in different tokens cannot share a cart
+    assert_eq!(
+        client.try_buy_many(&buyer, &soroban_sdk::vec![&env, (first, 100u64), (foreign, 100u64)], &100000),
+        Err(Ok(Error::TokenMismatch))
+    );
+    
+    assert_eq!(client.buy_many(&buyer, &cart, &32000), 32000);
+    
+    assert_eq!(client.get_listing(&first).remaining_tons, 400);
+    assert_eq!(client.get_listing(&second).remaining_tons, 450);
+    assert_eq!(client.get_listing(&third).remaining_tons, 300);
+    assert_eq!(carbon_client.balance_tons(&buyer), 350);
+    
+    // Each seller is paid once for all of their listings
+    assert_eq!(token_client.balance(&seller), 15600);
+    assert_eq!(token_client.balance(&other_seller), 15600);
+    assert_eq!(client.get_accrued_fees(&token_client.address), 800);
+    assert_eq!(token_client.balance(&buyer), 68000);
+}
+
+#[test]
+fn test_query_listings() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let other_seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&buyer, &100000);
+    let token = token_client.address.clone();
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let solar = String::from_str(&env, "PROJ-001");
+    let wind = String::from_str(&env, "PROJ-002");
+    let name = String::from_str(&env, "Project");
+    let no_royalty = carbon_credit::Royalty::default();
+    let solar_2022 = carbon_client.issue_credit(&seller, &solar, &name, &2022, &100, &no_royalty);
+    let solar_2024 = carbon_client.issue_credit(&seller, &solar, &name, &2024, &100, &no_royalty);
+    let wind_2023 = carbon_client.issue_credit(&other_seller, &wind, &name, &2023, &100, &no_royalty);
+    let wind_2024 = carbon_client.issue_credit(&other_seller, &wind, &name, &2024, &100, &no_royalty);
+    for credit_id in [solar_2022, solar_2024, wind_2023, wind_2024] {
+        verify_credit(&carbon_client, credit_id, &Address::generate(&env));
+    }
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token, &250);
+    
+    let first = client.create_listing(&seller, &solar_2022, &token, &80, &100, &None);
+    let second = client.create_listing(&seller, &solar_2024, &token, &120, &100, &None);
+    let third = client.create_listing(&other_seller, &wind_2023, &token, &100, &100, &None);
+    let fourth = client.create_listing(&other_seller, &wind_2024, &token, &150, &100, &None);
+    
+    // Pagination over all active listings returns full listings and a cursor to the rest
+    let page = client.get_listings(&1, &2);
+    assert_eq!(page.listings.len(), 2);
+    assert_eq!(page.listings.get_unchecked(0).listing_id, second);
+    assert_eq!(page.listings.get_unchecked(1).listing_id, third);
+    assert_eq!(page.listings.get_unchecked(1).project_id, wind);
+    assert_eq!(page.next_start, Some(3));
+    
+    let last_page = client.get_listings(&3, &2);
+    assert_eq!(last_page.listings.len(), 1);
+    assert_eq!(last_page.listings.get_unchecked(0).listing_id, fourth);
+    assert_eq!(last_page.next_start, None);
+    
+    let no_filter = ListingFilter {
+        seller: None,
+        project_id: None,
+        min_vintage: None,
+        max_vintage: None,
+        min_price: None,
+        max_price: None,
+    };
+    let ids = |page: ListingPage| {
+        let mut ids = soroban_sdk::Vec::new(&env);
+        for listing in page.listings.iter() {
+            ids.push_back(listing.listing_id);
+        }
+        ids
+    };
+    
+    let by_seller = ListingFilter { seller: Some(other_seller.clone()), ..no_filter.clone() };
+    assert_eq!(ids(client.query_listings(&by_seller, &0, &10)), soroban_sdk::vec![&env, third, fourth]);
+    
+    let by_project = ListingFilter { project_id: Some(solar.clone()), ..no_filter.clone() };
+    assert_eq!(ids(client.query_listings(&by_project, &0, &10)), soroban_sdk::vec![&env, first, second]);
+    
+    let by_vintage = ListingFilter { min_vintage: Some(2023), max_vintage: Some(2024), ..no_filter.clone() };
+    assert_eq!(ids(client.query_listings(&by_vintage, &0, &10)), soroban_sdk::vec![&env, third, second, fourth]);
+    
+    let by_price = ListingFilter { min_price: Some(100), max_price: Some(120), ..no_filter.clone() };
+    assert_eq!(ids(client.query_listings(&by_price, &0, &10)), soroban_sdk::vec![&env, second, third]);
+    
+    // Sold and cancelled listings leave the indexes
+    client.buy_credit(&buyer, &third, &100, &100);
+    client.cancel_listing(&seller, &first);
+    assert_eq!(ids(client.query_listings(&by_seller, &0, &10)), soroban_sdk::vec![&env, fourth]);
+    assert_eq!(ids(client.query_listings(&by_project, &0, &10)), soroban_sdk::vec![&env, second]);
+    assert_eq!(ids(client.get_listings(&0, &10)), soroban_sdk::vec![&env, second, fourth]);
+}
+
+#[test]
+fn test_private_listing() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let counterparty = Address::generate(&env);
+    let stranger = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&counterparty, &100000);
+    token_admin.mint(&stranger, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_client.address, &250);
+    
+    let listing_id = client.create_private_listing(&seller, &credit_id, &token_client.address, &90, &500, &counterparty);
+    assert_eq!(client.get_listing(&listing_id).allowed_buyer, Some(counterparty.clone()));
+    assert_eq!(carbon_client.get_owner(&credit_id), Some(contract_id));
+    
+    // Not visible in public views
+    assert_eq!(client.get_active_listings().len(), 0);
+    assert_eq!(client.get_listings(&0, &10).listings.len(), 0);
+    
+    assert_eq!(
+        client.try_buy_credit(&stranger, &listing_id, &500, &90),
+        Err(Ok(Error::NotAllowedBuyer))
+    );
+    
+    // The named buyer settles on-chain with the usual fee
+    client.buy_credit(&counterparty, &listing_id, &500, &90);
+    assert_eq!(client.get_listing(&listing_id).status, ListingStatus::Sold);
+    assert_eq!(carbon_client.get_owner(&credit_id), Some(counterparty));
+    assert_eq!(token_client.balance(&seller), 43875);
+    assert_eq!(client.get_accrued_fees(&token_client.address), 1125);
+}
+
+#[test]
+fn test_offers() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    let other_buyer = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&buyer, &100000);
+    token_admin.mint(&other_buyer, &100000);
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_client.address, &0);
+    
+    let listing_id = client.create_listing(&seller, &credit_id, &token_client.address, &100, &500, &None);
+    
+    // Offers escrow the full amount
+    let offer_id = client.make_offer(&buyer, &listing_id, &80, &200, &1000);
+    assert_eq!(token_client.balance(&buyer), 84000);
+    assert_eq!(client.get_offer(&offer_id).status, OfferStatus::Open);
+    
+    assert_eq!(
+        client.try_accept_offer(&buyer, &offer_id),
+        Err(Ok(Error::NotSeller))
+    );
+    
+    // Seller counters at 90; the buyer tops up the difference on acceptance
+    client.counter_offer(&seller, &offer_id, &90);
+    assert_eq!(
+        client.try_accept_offer(&seller, &offer_id),
+        Err(Ok(Error::OfferNotOpen))
+    );
+    client.accept_counter_offer(&buyer, &offer_id);
+    
+    let offer = client.get_offer(&offer_id);
+    assert_eq!(offer.status, OfferStatus::Accepted);
+    assert_eq!(token_client.balance(&buyer), 82000);
+    assert_eq!(token_client.balance(&seller), 18000);
+    assert_eq!(client.get_listing(&listing_id).remaining_tons, 300);
+    
+    // Seller accepts a second offer outright
+    let accepted_id = client.make_offer(&other_buyer, &listing_id, &85, &100, &1000);
+    client.accept_offer(&seller, &accepted_id);
+    assert_eq!(token_client.balance(&seller), 26500);
+    assert_eq!(client.get_listing(&listing_id).remaining_tons, 200);
+    
+    // Unaccepted offers are withdrawn or expire back to the buyer
+    let withdrawn_id = client.make_offer(&buyer, &listing_id, &50, &100, &1000);
+    assert_eq!(
+        client.try_withdraw_offer(&other_buyer, &withdrawn_id),
+        Err(Ok(Error::NotOfferBuyer))
+    );
+    client.withdraw_offer(&buyer, &withdrawn_id);
+    assert_eq!(client.get_offer(&withdrawn_id).status, OfferStatus::Withdrawn);
+    assert_eq!(token_client.balance(&buyer), 82000);
+    
+    let lapsed_id = client.make_offer(&other_buyer, &listing_id, &60, &100, &1000);
+    assert_eq!(
+        client.try_expire_offer(&lapsed_id),
+        Err(Ok(Error::OfferNotExpired))
+    );
+    
+    env.ledger().with_mut(|li| li.timestamp = 1000);
+    assert_eq!(
+        client.try_accept_offer(&seller, &lapsed_id),
+        Err(Ok(Error::OfferExpired))
+    );
+    client.expire_offer(&lapsed_id);
+    assert_eq!(client.get_offer(&lapsed_id).status, OfferStatus::Expired);
+    assert_eq!(token_client.balance(&other_buyer), 91500);
+    
+    assert_eq!(client.get_listing_offers(&listing_id).len(), 4);
+}
+
+#[test]
+fn test_reentrant_token() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (token_client, _) = create_token_contract(&env, &admin);
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token_client.address, &250);
+    
+    let malicious_id = env.register(ReentrantToken, ());
+    let malicious = ReentrantTokenClient::new(&env, &malicious_id);
+    client.add_payment_token(&admin, &malicious_id, &0);
+    
+    let listing_id = client.create_listing(&seller, &credit_id, &malicious_id, &100, &500, &None);
+    malicious.set_target(&contract_id, &buyer, &listing_id);
+    
+    // The token calls back into buy_credit mid-payment. The Soroban host forbids contract
+    // re-entry, so the nested call aborts before reaching the marketplace's TradeLock;
+    // the guard itself is exercised directly below.
+    client.buy_credit(&buyer, &listing_id, &200, &100);
+    assert_eq!(malicious.calls(), 1);
+    assert!(malicious.aborted());
+    
+    // Only the outer purchase was recorded, and the listing state was committed once
+    let listing = client.get_listing(&listing_id);
+    assert_eq!(listing.remaining_tons, 300);
+    assert_eq!(listing.status, ListingStatus::Active);
+    assert_eq!(client.get_listing_fills(&listing_id).len(), 1);
+    assert_eq!(carbon_client.get_credit(&listing.credit_id).amount_tons, 300);
+    
+    // A listing mid-settlement rejects further trades
+    env.as_contract(&contract_id, || {
+        env.storage().temporary().set(&DataKey::TradeLock(listing_id), &true);
+    });
+    assert_eq!(
+        client.try_buy_credit(&buyer, &listing_id, &100, &100),
+        Err(Ok(Error::TradeInProgress))
+    );
+    assert_eq!(
+        client.try_cancel_listing(&seller, &listing_id),
+        Err(Ok(Error::TradeInProgress))
+    );
+}
+
+#[test]
+fn test_listing_integrity() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let stranger = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&buyer, &100000);
+    let token = token_client.address.clone();
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let pending_id = issue_credit(&env, &carbon_client, &stranger, 500);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token, &250);
+    
+    assert_eq!(
+        client.try_create_listing(&seller, &42, &token, &100, &500, &None),
+        Err(Ok(Error::CreditNotFound))
+    );
+    assert_eq!(
+        client.try_create_listing(&stranger, &credit_id, &token, &100, &500, &None),
+        Err(Ok(Error::NotCreditOwner))
+    );
+    assert_eq!(
+        client.try_create_listing(&stranger, &pending_id, &token, &100, &500, &None),
+        Err(Ok(Error::NotVerified))
+    );
+    assert_eq!(
+        client.try_create_listing(&seller, &credit_id, &token, &100, &501, &None),
+        Err(Ok(Error::InvalidAmount))
+    );
+    
+    // Listing part of a credit escrows only the listed tons
+    let listing_id = client.create_listing(&seller, &credit_id, &token, &100, &200, &None);
+    let listed_id = client.get_listing(&listing_id).credit_id;
+    assert_eq!(carbon_client.get_credit(&listed_id).amount_tons, 200);
+    assert_eq!(carbon_client.get_owner(&listed_id), Some(contract_id));
+    assert_eq!(carbon_client.balance_tons(&seller), 300);
+    
+    // The escrowed credit cannot back a second listing
+    assert_eq!(
+        client.try_create_listing(&seller, &listed_id, &token, &100, &200, &None),
+        Err(Ok(Error::CreditAlreadyListed))
+    );
+    
+    // A partial fill moves the listing onto the unsold remainder
+    client.buy_credit(&buyer, &listing_id, &50, &100);
+    let remainder_id = client.get_listing(&listing_id).credit_id;
+    assert_eq!(
+        client.try_create_listing(&seller, &remainder_id, &token, &100, &150, &None),
+        Err(Ok(Error::CreditAlreadyListed))
+    );
+    
+    // Cancelling releases the credit for relisting
+    client.cancel_listing(&seller, &listing_id);
+    let relisted_id = client.create_listing(&seller, &remainder_id, &token, &110, &150, &None);
+    assert_eq!(client.get_listing(&relisted_id).credit_id, remainder_id);
+}
+
+#[test]
+fn test_trade_history() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let admin = Address::generate(&env);
+    let seller = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    
+    let (token_client, token_admin) = create_token_contract(&env, &admin);
+    token_admin.mint(&buyer, &100000);
+    let token = token_client.address.clone();
+    
+    let carbon_client = create_carbon_credit_contract(&env, &admin);
+    let credit_id = issue_verified_credit(&env, &carbon_client, &seller, 500);
+    
+    let contract_id = env.register(MarketplaceContract, ());
+    let client = MarketplaceContractClient::new(&env, &contract_id);
+    
+    client.initialize(&admin, &Address::generate(&env), &carbon_client.address, &token, &250);
+    
+    let project = StatsScope::Project(String::from_str(&env, "PROJ-001"));
+    assert_eq!(client.get_vwap(&project, &token, &7), None);
+    
+    let listing_id = client.create_listing(&seller, &credit_id, &token, &100, &500, &None);
+    client.buy_credit(&buyer, &listing_id, &100, &100);
+    
+    // Two days later the price has moved up
+    env.ledger().with_mut(|li| li.timestamp = 2 * 86400);
+    client.update_price(&seller, &listing_id, &120);
+    client.buy_credit(&buyer, &listing_id, &200, &120);
+    
+    let trades = client.get_trades(&0, &10);
+    assert_eq!(trades.len(), 2);
+    let first = trades.get(0).unwrap();
+    assert_eq!(first.trade_id, 1);
+    assert_eq!(first.buyer, buyer);
+    assert_eq!(first.seller, seller);
+    assert_eq!(first.payment_token, token);
+    assert_eq!(first.price_per_ton, 100);
+    assert_eq!(first.tons, 100);
+    assert_eq!(first.fee, 250);
+    assert_eq!(first.traded_at, 0);
+    assert_eq!(client.get_trades(&1, &10).get(0).unwrap().price_per_ton, 120);
+    assert_eq!(client.try_get_trade(&3).err(), Some(Ok(Error::TradeNotFound)));
+    
+    let stats = client.get_market_stats(&project, &token);
+    assert_eq!(stats.last_price, 120);
+    assert_eq!(stats.total_volume_tons, 300);
+    assert_eq!(stats.total_value, 34000);
+    assert_eq!(stats.trade_count, 2);
+    assert_eq!(client.get_market_stats(&StatsScope::Vintage(2024), &token), stats);
+    
+    // Rolling windows only count the days they cover
+    assert_eq!(client.get_vwap(&project, &token, &1), Some(120));
+    assert_eq!(client.get_vwap(&project, &token, &3), Some(113));
+    assert_eq!(client.get_vwap(&StatsScope::Vintage(2020), &token, &3), None);
+    assert_eq!(
+        client.try_get_vwap(&project, &token, &0),
+        Err(Ok(Error::InvalidWindow))
+    );
 }
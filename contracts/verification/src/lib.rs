@@ -1,5 +1,5 @@
 #![no_std]
-use soroban_sdk::{contract, contractimpl, contracttype, Address, Env, String, Symbol, Vec};
+use soroban_sdk::{contract, contractclient, contracterror, contractimpl, contracttype, Address, Env, String, Symbol, Vec};
 
 #[derive(Clone)]
 #[contracttype]
@@ -40,15 +40,36 @@ pub enum VerificationStatus {
     Rejected,
 }
 
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    AlreadyInitialized = 1,
+    NotInitialized = 2,
+    NotAuthorized = 3,
+    RequestNotFound = 4,
+    NotAssignedVerifier = 5,
+    InvalidRequestStatus = 6, // Request is not in a state that allows the action
+    NotVerifier = 7,
+    CreditNotFound = 8,
+}
+
+/// Subset of the carbon credit contract interface used to propagate outcomes
+#[contractclient(name = "CarbonCreditClient")]
+pub trait CarbonCreditInterface {
+    fn get_owner(env: Env, credit_id: u64) -> Option<Address>;
+    fn update_verification(env: Env, credit_id: u64, status: VerificationStatus, verifier: Address);
+}
+
 #[contract]
 pub struct VerificationContract;
 
 #[contractimpl]
 impl VerificationContract {
     /// Initialize the contract
-    pub fn initialize(env: Env, admin: Address, carbon_credit_contract: Address) {
+    pub fn initialize(env: Env, admin: Address, carbon_credit_contract: Address) -> Result<(), Error> {
         if env.storage().instance().has(&DataKey::Admin) {
-            panic!("Already initialized");
+            return Err(Error::AlreadyInitialized);
         }
         
         env.storage().instance().set(&DataKey::Admin, &admin);
@@ -57,37 +78,42 @@ impl VerificationContract {
         // Initialize empty verifiers list
         let verifiers: Vec<Address> = Vec::new(&env);
         env.storage().instance().set(&DataKey::Verifiers, &verifiers);
+        
+        Ok(())
     }
 
     /// Add a verifier (admin only)
-    pub fn add_verifier(env: Env, admin: Address, verifier: Address) {
+    pub fn add_verifier(env: Env, admin: Address, verifier: Address) -> Result<(), Error> {
         admin.require_auth();
         
-        let stored_admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        let stored_admin: Address = env.storage().instance().get(&DataKey::Admin).ok_or(Error::NotInitialized)?;
         if admin != stored_admin {
-            panic!("Not authorized");
+            return Err(Error::NotAuthorized);
         }
         
-        let mut verifiers: Vec<Address> = env.storage().instance().get(&DataKey::Verifiers).unwrap();
+        let mut verifiers = Self::load_verifiers(&env)?;
         verifiers.push_back(verifier.clone());
         env.storage().instance().set(&DataKey::Verifiers, &verifiers);
         
+        #[allow(deprecated)]
         env.events().publish(
             (Symbol::new(&env, "verifier_added"),),
             verifier
         );
+        
+        Ok(())
     }
 
     /// Remove a verifier (admin only)
-    pub fn remove_verifier(env: Env, admin: Address, verifier: Address) {
+    pub fn remove_verifier(env: Env, admin: Address, verifier: Address) -> Result<(), Error> {
         admin.require_auth();
         
-        let stored_admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        let stored_admin: Address = env.storage().instance().get(&DataKey::Admin).ok_or(Error::NotInitialized)?;
         if admin != stored_admin {
-            panic!("Not authorized");
+            return Err(Error::NotAuthorized);
         }
         
-        let mut verifiers: Vec<Address> = env.storage().instance().get(&DataKey::Verifiers).unwrap();
+        let verifiers = Self::load_verifiers(&env)?;
         let mut new_verifiers: Vec<Address> = Vec::new(&env);
         
         for v in verifiers.iter() {
@@ -98,22 +124,37 @@ impl VerificationContract {
         
         env.storage().instance().set(&DataKey::Verifiers, &new_verifiers);
         
+        #[allow(deprecated)]
         env.events().publish(
             (Symbol::new(&env, "verifier_removed"),),
             verifier
         );
+        
+        Ok(())
     }
 
-    /// Submit a verification request
+    /// Submit a verification request for an existing credit. A credit already under
+    /// request can only be resubmitted once its previous request was rejected.
     pub fn submit_verification(
         env: Env,
         requester: Address,
         credit_id: u64,
         project_id: String,
         evidence_uri: String,
-    ) {
+    ) -> Result<(), Error> {
         requester.require_auth();
         
+        if Self::carbon_credit_client(&env)?.get_owner(&credit_id).is_none() {
+            return Err(Error::CreditNotFound);
+        }
+        
+        // Open and approved requests cannot be overwritten
+        if let Some(existing) = env.storage().persistent().get::<DataKey, VerificationRequest>(&DataKey::VerificationRequest(credit_id)) {
+            if existing.status != RequestStatus::Rejected {
+                return Err(Error::InvalidRequestStatus);
+            }
+        }
+        
         let request = VerificationRequest {
             credit_id,
             requester,
@@ -127,19 +168,22 @@ impl VerificationContract {
         
         env.storage().persistent().set(&DataKey::VerificationRequest(credit_id), &request);
         
+        #[allow(deprecated)]
         env.events().publish(
             (Symbol::new(&env, "verification_submitted"),),
             credit_id
         );
+        
+        Ok(())
     }
 
     /// Assign a verifier to review a request (admin or verifier)
-    pub fn assign_verifier(env: Env, caller: Address, credit_id: u64, verifier: Address) {
+    pub fn assign_verifier(env: Env, caller: Address, credit_id: u64, verifier: Address) -> Result<(), Error> {
         caller.require_auth();
         
         // Check if caller is admin or a verifier
-        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
-        let verifiers: Vec<Address> = env.storage().instance().get(&DataKey::Verifiers).unwrap();
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).ok_or(Error::NotInitialized)?;
+        let verifiers = Self::load_verifiers(&env)?;
         
         let mut is_authorized = caller == admin;
         if !is_authorized {
@@ -152,38 +196,39 @@ impl VerificationContract {
         }
         
         if !is_authorized {
-            panic!("Not authorized");
+            return Err(Error::NotAuthorized);
         }
         
-        let mut request: VerificationRequest = env.storage()
-            .persistent()
-            .get(&DataKey::VerificationRequest(credit_id))
-            .unwrap();
+        if !Self::is_verifier(env.clone(), verifier.clone())? {
+            return Err(Error::NotVerifier);
+        }
+        
+        let mut request = Self::load_request(&env, credit_id)?;
+        
+        // Only open requests can be (re)assigned
+        if request.status != RequestStatus::Pending && request.status != RequestStatus::UnderReview {
+            return Err(Error::InvalidRequestStatus);
+        }
         
         request.status = RequestStatus::UnderReview;
         request.verifier = Some(verifier.clone());
         
         env.storage().persistent().set(&DataKey::VerificationRequest(credit_id), &request);
         
+        #[allow(deprecated)]
         env.events().publish(
             (Symbol::new(&env, "verifier_assigned"),),
             (credit_id, verifier)
         );
+        
+        Ok(())
     }
 
     /// Approve a verification request (verifier only)
-    pub fn approve_verification(env: Env, verifier: Address, credit_id: u64, notes: String) {
+    pub fn approve_verification(env: Env, verifier: Address, credit_id: u64, notes: String) -> Result<(), Error> {
         verifier.require_auth();
         
-        let mut request: VerificationRequest = env.storage()
-            .persistent()
-            .get(&DataKey::VerificationRequest(credit_id))
-            .unwrap();
-        
-        // Check if verifier is assigned to this request
-        if request.verifier != Some(verifier.clone()) {
-            panic!("Not the assigned verifier");
-        }
+        let mut request = Self::load_reviewable_request(&env, &verifier, credit_id)?;
         
         request.status = RequestStatus::Approved;
         request.verified_at = Some(env.ledger().timestamp());
@@ -191,33 +236,23 @@ impl VerificationContract {
         
         env.storage().persistent().set(&DataKey::VerificationRequest(credit_id), &request);
         
-        // Update carbon credit contract
-        let carbon_credit_contract: Address = env.storage()
-            .instance()
-            .get(&DataKey::CarbonCreditContract)
-            .unwrap();
+        // Mark the credit as verified on the carbon credit contract
+        Self::carbon_credit_client(&env)?.update_verification(&credit_id, &VerificationStatus::Verified, &verifier);
         
-        // Call update_verification on carbon credit contract
-        // This requires proper cross-contract call setup
+        #[allow(deprecated)]
         env.events().publish(
             (Symbol::new(&env, "verification_approved"),),
             (credit_id, verifier)
         );
+        
+        Ok(())
     }
 
     /// Reject a verification request (verifier only)
-    pub fn reject_verification(env: Env, verifier: Address, credit_id: u64, reason: String) {
+    pub fn reject_verification(env: Env, verifier: Address, credit_id: u64, reason: String) -> Result<(), Error> {
         verifier.require_auth();
         
-        let mut request: VerificationRequest = env.storage()
-            .persistent()
-            .get(&DataKey::VerificationRequest(credit_id))
-            .unwrap();
-        
-        // Check if verifier is assigned to this request
-        if request.verifier != Some(verifier.clone()) {
-            panic!("Not the assigned verifier");
-        }
+        let mut request = Self::load_reviewable_request(&env, &verifier, credit_id)?;
         
         request.status = RequestStatus::Rejected;
         request.verified_at = Some(env.ledger().timestamp());
@@ -225,36 +260,82 @@ impl VerificationContract {
         
         env.storage().persistent().set(&DataKey::VerificationRequest(credit_id), &request);
         
+        // Mark the credit as rejected on the carbon credit contract
+        Self::carbon_credit_client(&env)?.update_verification(&credit_id, &VerificationStatus::Rejected, &verifier);
+        
+        #[allow(deprecated)]
         env.events().publish(
             (Symbol::new(&env, "verification_rejected"),),
             (credit_id, verifier)
         );
+        
+        Ok(())
     }
 
     /// Get verification request details
-    pub fn get_request(env: Env, credit_id: u64) -> VerificationRequest {
-        env.storage()
-            .persistent()
-            .get(&DataKey::VerificationRequest(credit_id))
-            .unwrap()
+    pub fn get_request(env: Env, credit_id: u64) -> Result<VerificationRequest, Error> {
+        Self::load_request(&env, credit_id)
     }
 
     /// Check if address is a verifier
-    pub fn is_verifier(env: Env, address: Address) -> bool {
-        let verifiers: Vec<Address> = env.storage().instance().get(&DataKey::Verifiers).unwrap();
+    pub fn is_verifier(env: Env, address: Address) -> Result<bool, Error> {
+        let verifiers = Self::load_verifiers(&env)?;
         
         for verifier in verifiers.iter() {
             if verifier == address {
-                return true;
+                return Ok(true);
             }
         }
         
-        false
+        Ok(false)
     }
 
     /// Get all verifiers
-    pub fn get_verifiers(env: Env) -> Vec<Address> {
-        env.storage().instance().get(&DataKey::Verifiers).unwrap()
+    pub fn get_verifiers(env: Env) -> Result<Vec<Address>, Error> {
+        Self::load_verifiers(&env)
+    }
+
+    // Helper function to load the verifier list
+    fn load_verifiers(env: &Env) -> Result<Vec<Address>, Error> {
+        env.storage().instance().get(&DataKey::Verifiers).ok_or(Error::NotInitialized)
+    }
+
+    // Helper function to load a verification request
+    fn load_request(env: &Env, credit_id: u64) -> Result<VerificationRequest, Error> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::VerificationRequest(credit_id))
+            .ok_or(Error::RequestNotFound)
+    }
+
+    // Helper function to load a request under review by `verifier`, who must still be registered
+    fn load_reviewable_request(env: &Env, verifier: &Address, credit_id: u64) -> Result<VerificationRequest, Error> {
+        let request = Self::load_request(env, credit_id)?;
+        
+        if request.status != RequestStatus::UnderReview {
+            return Err(Error::InvalidRequestStatus);
+        }
+        
+        // Check if verifier is assigned to this request
+        if request.verifier != Some(verifier.clone()) {
+            return Err(Error::NotAssignedVerifier);
+        }
+        
+        if !Self::is_verifier(env.clone(), verifier.clone())? {
+            return Err(Error::NotVerifier);
+        }
+        
+        Ok(request)
+    }
+
+    // Helper function to build a client for the carbon credit contract
+    fn carbon_credit_client(env: &Env) -> Result<CarbonCreditClient<'_>, Error> {
+        let carbon_credit_contract: Address = env.storage()
+            .instance()
+            .get(&DataKey::CarbonCreditContract)
+            .ok_or(Error::NotInitialized)?;
+        
+        Ok(CarbonCreditClient::new(env, &carbon_credit_contract))
     }
 }
 
@@ -1,11 +1,19 @@
 #![cfg(test)]
 use super::*;
+use carbon_credit::{CarbonCreditContract, CarbonCreditContractClient, Royalty, VerificationStatus as CreditVerificationStatus};
 use soroban_sdk::{testutils::Address as _, Address, Env, String};
 
+fn create_carbon_credit_contract<'a>(env: &Env, admin: &Address, verification_contract: &Address) -> CarbonCreditContractClient<'a> {
+    let contract_id = env.register(CarbonCreditContract, ());
+    let client = CarbonCreditContractClient::new(env, &contract_id);
+    client.initialize(admin, verification_contract);
+    client
+}
+
 #[test]
 fn test_initialize_and_add_verifier() {
     let env = Env::default();
-    let contract_id = env.register_contract(None, VerificationContract);
+    let contract_id = env.register(VerificationContract, ());
     let client = VerificationContractClient::new(&env, &contract_id);
     
     let admin = Address::generate(&env);
@@ -29,21 +37,30 @@ fn test_initialize_and_add_verifier() {
 #[test]
 fn test_submit_and_approve_verification() {
     let env = Env::default();
-    let contract_id = env.register_contract(None, VerificationContract);
+    let contract_id = env.register(VerificationContract, ());
     let client = VerificationContractClient::new(&env, &contract_id);
     
     let admin = Address::generate(&env);
-    let carbon_credit_contract = Address::generate(&env);
+    let carbon_client = create_carbon_credit_contract(&env, &admin, &contract_id);
     let verifier = Address::generate(&env);
     let requester = Address::generate(&env);
     
-    client.initialize(&admin, &carbon_credit_contract);
+    client.initialize(&admin, &carbon_client.address);
     
     env.mock_all_auths();
     client.add_verifier(&admin, &verifier);
     
+    // Issue the credit to be verified
+    let credit_id = carbon_client.issue_credit(
+        &requester,
+        &String::from_str(&env, "PROJ-001"),
+        &String::from_str(&env, "Solar Farm Project"),
+        &2024,
+        &1000,
+        &Royalty::default(),
+    );
+    
     // Submit verification request
-    let credit_id = 1u64;
     client.submit_verification(
         &requester,
         &credit_id,
@@ -64,25 +81,36 @@ fn test_submit_and_approve_verification() {
     // Check request status
     let request = client.get_request(&credit_id);
     assert_eq!(request.status, RequestStatus::Approved);
+    
+    // Check the outcome reached the carbon credit contract
+    assert!(carbon_client.is_verified(&credit_id));
 }
 
 #[test]
 fn test_reject_verification() {
     let env = Env::default();
-    let contract_id = env.register_contract(None, VerificationContract);
+    let contract_id = env.register(VerificationContract, ());
     let client = VerificationContractClient::new(&env, &contract_id);
     
     let admin = Address::generate(&env);
-    let carbon_credit_contract = Address::generate(&env);
+    let carbon_client = create_carbon_credit_contract(&env, &admin, &contract_id);
     let verifier = Address::generate(&env);
     let requester = Address::generate(&env);
     
-    client.initialize(&admin, &carbon_credit_contract);
+    client.initialize(&admin, &carbon_client.address);
     
     env.mock_all_auths();
     client.add_verifier(&admin, &verifier);
     
-    let credit_id = 2u64;
+    let credit_id = carbon_client.issue_credit(
+        &requester,
+        &String::from_str(&env, "PROJ-002"),
+        &String::from_str(&env, "Wind Farm"),
+        &2024,
+        &500,
+        &Royalty::default(),
+    );
+    
     client.submit_verification(
         &requester,
         &credit_id,
@@ -101,4 +129,187 @@ fn test_reject_verification() {
     
     let request = client.get_request(&credit_id);
     assert_eq!(request.status, RequestStatus::Rejected);
+    
+    let credit = carbon_client.get_credit(&credit_id);
+    assert_eq!(credit.verification_status, CreditVerificationStatus::Rejected);
+}
+
+#[test]
+fn test_approval_enables_retirement() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let contract_id = env.register(VerificationContract, ());
+    let client = VerificationContractClient::new(&env, &contract_id);
+    
+    let admin = Address::generate(&env);
+    let carbon_client = create_carbon_credit_contract(&env, &admin, &contract_id);
+    let verifier = Address::generate(&env);
+    let issuer = Address::generate(&env);
+    
+    client.initialize(&admin, &carbon_client.address);
+    client.add_verifier(&admin, &verifier);
+    
+    let credit_id = carbon_client.issue_credit(
+        &issuer,
+        &String::from_str(&env, "PROJ-003"),
+        &String::from_str(&env, "Reforestation"),
+        &2023,
+        &2000,
+        &Royalty::default(),
+    );
+    
+    client.submit_verification(
+        &issuer,
+        &credit_id,
+        &String::from_str(&env, "PROJ-003"),
+        &String::from_str(&env, "https://evidence.example.com/proj003"),
+    );
+    client.assign_verifier(&admin, &credit_id, &verifier);
+    client.approve_verification(&verifier, &credit_id, &String::from_str(&env, "Verified"));
+    
+    // The approving verifier is recorded for royalty payouts
+    assert_eq!(carbon_client.get_credit(&credit_id).verifier, Some(verifier));
+    
+    // Credit can be retired without faking the verification status
+    carbon_client.retire_credit(
+        &issuer,
+        &credit_id,
+        &String::from_str(&env, "Issuer"),
+        &String::from_str(&env, "Voluntary offset"),
+        &String::from_str(&env, "2023"),
+    );
+    assert_eq!(carbon_client.get_owner(&credit_id), None);
+}
+
+#[test]
+fn test_error_codes() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let contract_id = env.register(VerificationContract, ());
+    let client = VerificationContractClient::new(&env, &contract_id);
+    
+    let admin = Address::generate(&env);
+    let carbon_client = create_carbon_credit_contract(&env, &admin, &contract_id);
+    let verifier = Address::generate(&env);
+    let other_verifier = Address::generate(&env);
+    let requester = Address::generate(&env);
+    
+    client.initialize(&admin, &carbon_client.address);
+    client.add_verifier(&admin, &verifier);
+    
+    assert_eq!(
+        client.try_add_verifier(&requester, &other_verifier),
+        Err(Ok(Error::NotAuthorized))
+    );
+    assert_eq!(client.try_get_request(&7).err(), Some(Ok(Error::RequestNotFound)));
+    
+    let project_id = String::from_str(&env, "PROJ-007");
+    let evidence_uri = String::from_str(&env, "https://evidence.example.com/proj007");
+    assert_eq!(
+        client.try_submit_verification(&requester, &7, &project_id, &evidence_uri),
+        Err(Ok(Error::CreditNotFound))
+    );
+    
+    let credit_id = carbon_client.issue_credit(
+        &requester,
+        &project_id,
+        &String::from_str(&env, "Peatland"),
+        &2024,
+        &100,
+        &Royalty::default(),
+    );
+    client.submit_verification(&requester, &credit_id, &project_id, &evidence_uri);
+    client.assign_verifier(&admin, &credit_id, &verifier);
+    
+    assert_eq!(
+        client.try_approve_verification(&other_verifier, &credit_id, &String::from_str(&env, "")),
+        Err(Ok(Error::NotAssignedVerifier))
+    );
+}
+
+#[test]
+fn test_review_lifecycle() {
+    let env = Env::default();
+    env.mock_all_auths();
+    
+    let contract_id = env.register(VerificationContract, ());
+    let client = VerificationContractClient::new(&env, &contract_id);
+    
+    let admin = Address::generate(&env);
+    let carbon_client = create_carbon_credit_contract(&env, &admin, &contract_id);
+    let verifier = Address::generate(&env);
+    let other_verifier = Address::generate(&env);
+    let issuer = Address::generate(&env);
+    
+    client.initialize(&admin, &carbon_client.address);
+    client.add_verifier(&admin, &verifier);
+    client.add_verifier(&admin, &other_verifier);
+    
+    let credit_id = carbon_client.issue_credit(
+        &issuer,
+        &String::from_str(&env, "PROJ-004"),
+        &String::from_str(&env, "Mangroves"),
+        &2023,
+        &1000,
+        &Royalty::default(),
+    );
+    let project_id = String::from_str(&env, "PROJ-004");
+    let evidence_uri = String::from_str(&env, "https://evidence.example.com/proj004");
+    let notes = String::from_str(&env, "");
+    
+    client.submit_verification(&issuer, &credit_id, &project_id, &evidence_uri);
+    assert_eq!(
+        client.try_submit_verification(&issuer, &credit_id, &project_id, &evidence_uri),
+        Err(Ok(Error::InvalidRequestStatus))
+    );
+    
+    // Requests must be assigned to a registered verifier before review
+    assert_eq!(
+        client.try_approve_verification(&verifier, &credit_id, &notes),
+        Err(Ok(Error::InvalidRequestStatus))
+    );
+    assert_eq!(
+        client.try_assign_verifier(&admin, &credit_id, &Address::generate(&env)),
+        Err(Ok(Error::NotVerifier))
+    );
+    
+    // A request under review cannot be reset by resubmitting it
+    client.assign_verifier(&admin, &credit_id, &verifier);
+    assert_eq!(
+        client.try_submit_verification(&Address::generate(&env), &credit_id, &project_id, &evidence_uri),
+        Err(Ok(Error::InvalidRequestStatus))
+    );
+    assert_eq!(client.get_request(&credit_id).verifier, Some(verifier.clone()));
+    
+    // A verifier removed after assignment can no longer decide
+    client.remove_verifier(&admin, &verifier);
+    assert_eq!(
+        client.try_approve_verification(&verifier, &credit_id, &notes),
+        Err(Ok(Error::NotVerifier))
+    );
+    
+    // A rejected request can be resubmitted for a fresh review
+    client.assign_verifier(&admin, &credit_id, &other_verifier);
+    client.reject_verification(&other_verifier, &credit_id, &notes);
+    client.submit_verification(&issuer, &credit_id, &project_id, &evidence_uri);
+    assert_eq!(client.get_request(&credit_id).status, RequestStatus::Pending);
+    
+    // Once approved, the outcome is final
+    client.assign_verifier(&admin, &credit_id, &other_verifier);
+    client.approve_verification(&other_verifier, &credit_id, &notes);
+    assert_eq!(
+        client.try_reject_verification(&other_verifier, &credit_id, &notes),
+        Err(Ok(Error::InvalidRequestStatus))
+    );
+    assert_eq!(
+        client.try_assign_verifier(&admin, &credit_id, &other_verifier),
+        Err(Ok(Error::InvalidRequestStatus))
+    );
+    assert_eq!(
+        client.try_submit_verification(&issuer, &credit_id, &project_id, &evidence_uri),
+        Err(Ok(Error::InvalidRequestStatus))
+    );
+    assert!(carbon_client.is_verified(&credit_id));
 }